@@ -0,0 +1,449 @@
+//!
+//! Photo and video albums, outside of Messenger
+//!
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use rayon::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::posts::{ExifData, MediaMetadata};
+use super::{parse_lenient, ActivityTypes, ParseMode};
+use crate::datasource::{DataSource, TimelineEvent};
+use crate::source::Export;
+use crate::{MagicError, Skippable};
+
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Album {
+    pub name: String,
+    #[serde(default)]
+    pub photos: Vec<AlbumMedia>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cover_photo: Option<AlbumMedia>,
+    #[schemars(with = "Option<i64>")]
+    #[serde(
+        with = "chrono::serde::ts_seconds_option",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub last_modified_timestamp: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub comments: Vec<AlbumComment>,
+}
+
+/// A photo or video in an album, the list's called `photos` either way
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct AlbumMedia {
+    pub uri: String,
+    #[schemars(with = "Option<i64>")]
+    #[serde(
+        with = "chrono::serde::ts_seconds_option",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub creation_timestamp: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub media_metadata: Option<MediaMetadata>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub comments: Vec<AlbumComment>,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct AlbumComment {
+    #[schemars(with = "i64")]
+    #[serde(with = "chrono::serde::ts_seconds")]
+    pub timestamp: DateTime<Utc>,
+    pub comment: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+}
+
+impl AlbumMedia {
+    pub fn exif(&self) -> impl Iterator<Item = &ExifData> {
+        self.media_metadata
+            .iter()
+            .flat_map(|metadata| {
+                metadata
+                    .photo_metadata
+                    .iter()
+                    .chain(metadata.video_metadata.iter())
+            })
+            .flat_map(|metadata| metadata.exif_data.iter())
+    }
+
+    /// When it was taken according to the EXIF data, otherwise when it was uploaded
+    pub fn taken(&self) -> Option<DateTime<Utc>> {
+        self.exif()
+            .find_map(|exif| exif.taken_timestamp)
+            .or(self.creation_timestamp)
+    }
+}
+
+pub fn parse_album_file(
+    export: &Export,
+    path: &Path,
+    mode: ParseMode,
+) -> Result<Album, MagicError> {
+    let value: Value = serde_json::from_reader(export.open(path)?)
+        .map_err(|err| MagicError::Generic(format!("{}: {}", path.display(), err)))?;
+    let parse = |value: Value| {
+        serde_json::from_value::<Album>(value)
+            .map_err(|err| MagicError::Generic(format!("{}: {}", path.display(), err)))
+    };
+    match (mode, value) {
+        // photos are parsed one at a time so a bad one doesn't lose the album
+        (ParseMode::Lenient, Value::Object(mut album)) => {
+            let photos = match album.remove("photos") {
+                Some(Value::Array(photos)) => photos,
+                _ => Vec::new(),
+            };
+            let mut album = parse(Value::Object(album))?;
+            album.photos = parse_lenient(photos, path);
+            Ok(album)
+        }
+        (_, value) => parse(value),
+    }
+}
+
+/// Every album in the export
+pub fn find_albums(export: &Export, mode: ParseMode) -> Result<Vec<Album>, MagicError> {
+    let mut albums = Vec::new();
+    for path in DataSource::discover(&AlbumsSource, export) {
+        match (mode, parse_album_file(export, &path, mode)) {
+            (_, Ok(album)) => albums.push(album),
+            (ParseMode::Lenient, Err(err)) => eprintln!("Skipping album: {:?}", err),
+            (ParseMode::Strict, Err(err)) => return Err(err),
+        }
+    }
+    Ok(albums)
+}
+
+/// Albums, for the [registry](crate::datasource::registry)
+pub struct AlbumsSource;
+
+impl Skippable for AlbumsSource {
+    fn path(&self) -> PathBuf {
+        ActivityTypes::Albums.path()
+    }
+
+    fn skippable(&self, export: &Export) -> bool {
+        ActivityTypes::Albums.skippable(export)
+    }
+}
+
+impl DataSource for AlbumsSource {
+    type Record = Album;
+
+    fn name(&self) -> &'static str {
+        "albums"
+    }
+
+    fn handles(&self, path: &Path) -> bool {
+        path.parent() == Some(ActivityTypes::Albums.path().as_path())
+            && path.extension().is_some_and(|ext| ext == "json")
+    }
+
+    fn parse(&self, export: &Export, path: &Path) -> Result<Vec<Album>, MagicError> {
+        Ok(vec![parse_album_file(export, path, ParseMode::Lenient)?])
+    }
+
+    fn event(&self, _path: &Path, album: &Album) -> Option<TimelineEvent> {
+        let timestamp = album
+            .last_modified_timestamp
+            .or_else(|| album.photos.iter().filter_map(|photo| photo.taken()).max())?;
+        Some(TimelineEvent {
+            timestamp,
+            source: "albums",
+            actor: None,
+            summary: format!("Album {} ({} items)", album.name, album.photos.len()),
+        })
+    }
+
+    fn stats(&self, albums: &[Album]) -> BTreeMap<String, usize> {
+        BTreeMap::from([
+            ("albums".to_string(), albums.len()),
+            (
+                "items".to_string(),
+                albums.iter().map(|album| album.photos.len()).sum(),
+            ),
+            (
+                "comments".to_string(),
+                albums
+                    .iter()
+                    .flat_map(|album| album.photos.iter())
+                    .map(|photo| photo.comments.len())
+                    .sum(),
+            ),
+        ])
+    }
+}
+
+/// Album names can have anything in them, this makes one safe to use as a folder name
+fn folder_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    match name.trim().trim_matches('.') {
+        "" => "untitled".to_string(),
+        name => name.to_string(),
+    }
+}
+
+/// What goes next to each file, when there's a caption or comments to keep
+#[derive(Serialize, Debug)]
+struct Sidecar<'a> {
+    album: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    title: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    caption: Option<&'a str>,
+    taken: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    camera: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    upload_ip: Option<&'a str>,
+    comments: &'a [AlbumComment],
+}
+
+/// Copies every album's photos and videos to `<output>/<album>/<year>/<month>/`, named by when
+/// they were taken, with a `.json` sidecar for any caption and comments
+pub fn reorg_albums(export: &Export, mode: ParseMode, output: PathBuf) -> Result<(), MagicError> {
+    let albums = find_albums(export, mode)?;
+    // names are worked out up front so two items from the same second with the same file name
+    // get numbered, the same as pages in posts::site
+    let mut seen: BTreeMap<PathBuf, usize> = BTreeMap::new();
+    let mut items = Vec::new();
+    for album in albums.iter() {
+        for photo in album.photos.iter() {
+            let timestamp = match photo.taken() {
+                Some(timestamp) => timestamp,
+                None => {
+                    eprintln!("No date for {}, skipping it", photo.uri);
+                    continue;
+                }
+            };
+            let datepath = output
+                .join(folder_name(&album.name))
+                .join(timestamp.format("%Y/%m").to_string());
+            let filepath = Path::new(&photo.uri);
+            let base = datepath.join(format!(
+                "{}-{}",
+                timestamp.format("%Y-%m-%d-%H-%M-%S"),
+                filepath
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .unwrap_or_default()
+            ));
+            let count = seen.entry(base.clone()).or_default();
+            *count += 1;
+            let new_filename = match count {
+                1 => base,
+                n => {
+                    let stem = base
+                        .file_stem()
+                        .and_then(|stem| stem.to_str())
+                        .unwrap_or_default();
+                    let name = match base.extension().and_then(|ext| ext.to_str()) {
+                        Some(ext) => format!("{}-{}.{}", stem, n, ext),
+                        None => format!("{}-{}", stem, n),
+                    };
+                    datepath.join(name)
+                }
+            };
+            items.push((album, photo, timestamp, new_filename));
+        }
+    }
+
+    items
+        .par_iter()
+        .try_for_each(|(album, photo, timestamp, new_filename)| {
+            let timestamp = *timestamp;
+            let filepath = PathBuf::from(&photo.uri);
+            if let Some(datepath) = new_filename.parent() {
+                std::fs::create_dir_all(datepath).map_err(|err| {
+                    MagicError::Generic(format!("{}: {}", datepath.display(), err))
+                })?;
+            }
+            export.copy(&filepath, new_filename)?;
+
+            let caption = photo.description.as_deref().filter(|c| !c.is_empty());
+            if caption.is_none() && photo.comments.is_empty() {
+                return Ok(());
+            }
+            let exif = photo.exif().next();
+            let camera = exif.and_then(|exif| {
+                let parts: Vec<&str> = [exif.camera_make.as_deref(), exif.camera_model.as_deref()]
+                    .into_iter()
+                    .flatten()
+                    .collect();
+                (!parts.is_empty()).then(|| parts.join(" "))
+            });
+            let sidecar = Sidecar {
+                album: &album.name,
+                // photos in an album are usually titled with the album's name, that's not much use
+                title: photo
+                    .title
+                    .as_deref()
+                    .filter(|title| !title.is_empty() && *title != album.name),
+                caption,
+                taken: timestamp,
+                camera,
+                upload_ip: exif.and_then(|exif| exif.upload_ip.as_deref()),
+                comments: &photo.comments,
+            };
+            let mut sidecar_path = new_filename.clone().into_os_string();
+            sidecar_path.push(".json");
+            let contents = serde_json::to_string_pretty(&sidecar)
+                .map_err(|err| MagicError::Generic(err.to_string()))?;
+            std::fs::write(&sidecar_path, contents).map_err(|err| {
+                MagicError::Generic(format!("{}: {}", Path::new(&sidecar_path).display(), err))
+            })
+        })?;
+
+    // the album's own description and comments go in the album's folder
+    for album in albums.iter() {
+        let description = album.description.as_deref().filter(|d| !d.is_empty());
+        if description.is_none() && album.comments.is_empty() {
+            continue;
+        }
+        let folder = output.join(folder_name(&album.name));
+        std::fs::create_dir_all(&folder)
+            .map_err(|err| MagicError::Generic(format!("{}: {}", folder.display(), err)))?;
+        let sidecar = serde_json::json!({
+            "album": album.name,
+            "description": description,
+            "comments": album.comments,
+        });
+        let path = folder.join("album.json");
+        let contents = serde_json::to_string_pretty(&sidecar)
+            .map_err(|err| MagicError::Generic(err.to_string()))?;
+        std::fs::write(&path, contents)
+            .map_err(|err| MagicError::Generic(format!("{}: {}", path.display(), err)))?;
+    }
+    Ok(())
+}
+
+/// Lists the albums, with how many photos and videos are in each
+pub fn list_albums(export: &Export, mode: ParseMode) -> Result<(), MagicError> {
+    let albums = find_albums(export, mode)?;
+    for album in albums.iter() {
+        let taken: Vec<DateTime<Utc>> = album.photos.iter().filter_map(|p| p.taken()).collect();
+        let format_time = |time: Option<&DateTime<Utc>>| {
+            time.map(|time| time.format("%Y-%m-%d").to_string())
+                .unwrap_or_default()
+        };
+        println!(
+            "{}: {} items, {} - {}",
+            album.name,
+            album.photos.len(),
+            format_time(taken.iter().min()),
+            format_time(taken.iter().max())
+        );
+    }
+    println!("Total: {} albums", albums.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{folder_name, reorg_albums};
+    use crate::activity::{ActivityTypes, ParseMode};
+    use crate::source::{DirectorySource, Export};
+    use crate::test_util::{temp_dir, write_file};
+    use crate::Skippable;
+
+    static TEST_ALBUM: &str = r#"{
+        "name": "Holiday: 2020",
+        "photos": [
+            {
+                "uri": "your_activity_across_facebook/posts/media/Holiday_1/1.jpg",
+                "creation_timestamp": 1600000000,
+                "media_metadata": {"photo_metadata": {"exif_data": [{"taken_timestamp": 1590000000, "camera_make": "Acme", "camera_model": "One", "upload_ip": "192.0.2.1"}]}},
+                "title": "Holiday: 2020",
+                "description": "The beach",
+                "comments": [{"timestamp": 1600000500, "comment": "Nice", "author": "Alice"}]
+            },
+            {
+                "uri": "your_activity_across_facebook/posts/media/Holiday_1/2.mp4",
+                "creation_timestamp": 1600000100
+            },
+            {
+                "uri": "your_activity_across_facebook/posts/media/Holiday_2/1.jpg",
+                "creation_timestamp": 1600000300,
+                "media_metadata": {"photo_metadata": {"exif_data": [{"taken_timestamp": 1590000000}]}}
+            },
+            {"uri": "broken"}
+        ],
+        "last_modified_timestamp": 1600000200,
+        "description": "Two weeks away",
+        "comments": [{"timestamp": 1600000600, "comment": "Jealous", "author": "Bob"}]
+    }"#;
+
+    #[test]
+    fn test_reorg_albums() {
+        assert_eq!(folder_name("a/b: c"), "a_b_ c");
+        assert_eq!(folder_name(".."), "untitled");
+
+        let temp = temp_dir();
+        let root = temp.path().to_path_buf();
+        let media = Path::new("your_activity_across_facebook/posts/media");
+        write_file(
+            &root,
+            ActivityTypes::Albums.path().join("0.json"),
+            TEST_ALBUM,
+        );
+        write_file(&root, media.join("Holiday_1/1.jpg"), b"jpeg");
+        write_file(&root, media.join("Holiday_1/2.mp4"), b"mp4");
+        write_file(&root, media.join("Holiday_2/1.jpg"), b"other jpeg");
+        let export = Export::from_source(DirectorySource::new(&root));
+        let output = root.join("output");
+
+        reorg_albums(&export, ParseMode::Strict, output.clone()).unwrap();
+        let album = output.join("Holiday_ 2020");
+        // dated by the EXIF taken time, not when it was uploaded
+        let photo = album.join("2020/05/2020-05-20-18-40-00-1.jpg");
+        assert!(photo.exists());
+        assert!(album.join("2020/09/2020-09-13-12-28-20-2.mp4").exists());
+        // same second and file name, so it's numbered rather than overwriting the first
+        assert_eq!(std::fs::read(&photo).unwrap(), b"jpeg");
+        assert_eq!(
+            std::fs::read(album.join("2020/05/2020-05-20-18-40-00-1-2.jpg")).unwrap(),
+            b"other jpeg"
+        );
+        let sidecar: serde_json::Value = serde_json::from_str(
+            &std::fs::read_to_string(album.join("2020/05/2020-05-20-18-40-00-1.jpg.json")).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(sidecar["caption"], "The beach");
+        assert_eq!(sidecar["camera"], "Acme One");
+        assert_eq!(sidecar["comments"][0]["author"], "Alice");
+        assert!(sidecar.get("title").is_none());
+        assert!(!album
+            .join("2020/09/2020-09-13-12-28-20-2.mp4.json")
+            .exists());
+        let sidecar: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(album.join("album.json")).unwrap())
+                .unwrap();
+        assert_eq!(sidecar["description"], "Two weeks away");
+        assert_eq!(sidecar["comments"][0]["comment"], "Jealous");
+    }
+}
@@ -0,0 +1,309 @@
+//!
+//! Bug bounty reports, what was submitted, where it got to and what it paid
+//!
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::{parse_lenient, ActivityTypes, ParseMode};
+use crate::datasource::{DataSource, TimelineEvent};
+use crate::source::Export;
+use crate::{MagicError, OutputFormat, Skippable};
+
+/// The key the reports are under in the file
+static REPORTS_KEY: &str = "bug_bounty_reports_v2";
+
+#[derive(Deserialize, Serialize, JsonSchema, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct BugBountyFile {
+    pub bug_bounty_reports_v2: Vec<BugBountyReport>,
+}
+
+#[derive(
+    Deserialize, Serialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportStatus {
+    Open,
+    Triaged,
+    NeedsInfo,
+    Duplicate,
+    NotApplicable,
+    Resolved,
+    Paid,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct BugBountyPayout {
+    pub amount: f64,
+    pub currency: String,
+    #[schemars(with = "i64")]
+    #[serde(with = "chrono::serde::ts_seconds")]
+    pub timestamp: DateTime<Utc>,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct BugBountyReport {
+    pub report_id: String,
+    pub title: String,
+    pub status: ReportStatus,
+    #[schemars(with = "i64")]
+    #[serde(with = "chrono::serde::ts_seconds")]
+    pub submitted_timestamp: DateTime<Utc>,
+    #[schemars(with = "Option<i64>")]
+    #[serde(
+        with = "chrono::serde::ts_seconds_option",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub updated_timestamp: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub payouts: Vec<BugBountyPayout>,
+}
+
+impl BugBountyReport {
+    /// What the report paid, per currency
+    pub fn paid(&self) -> BTreeMap<&str, f64> {
+        let mut paid = BTreeMap::new();
+        for payout in self.payouts.iter() {
+            *paid.entry(payout.currency.as_str()).or_default() += payout.amount;
+        }
+        paid
+    }
+}
+
+/// Parse a bug bounty file. Lenient parsing leaves out reports that don't fit rather than
+/// failing the whole file.
+pub fn parse_bug_bounty_file(
+    export: &Export,
+    path: &Path,
+    mode: ParseMode,
+) -> Result<Vec<BugBountyReport>, MagicError> {
+    let value: Value = serde_json::from_reader(export.open(path)?)
+        .map_err(|err| MagicError::Generic(format!("{}: {}", path.display(), err)))?;
+    match mode {
+        ParseMode::Strict => serde_json::from_value::<BugBountyFile>(value)
+            .map(|file| file.bug_bounty_reports_v2)
+            .map_err(|err| MagicError::Generic(format!("{}: {}", path.display(), err))),
+        ParseMode::Lenient => {
+            let reports = match value.get(REPORTS_KEY).and_then(Value::as_array) {
+                Some(reports) => reports.clone(),
+                None => {
+                    eprintln!("{}: no {} list, skipping", path.display(), REPORTS_KEY);
+                    Vec::new()
+                }
+            };
+            Ok(parse_lenient(reports, path))
+        }
+    }
+}
+
+/// Every report in the export, oldest first
+pub fn find_reports(export: &Export, mode: ParseMode) -> Result<Vec<BugBountyReport>, MagicError> {
+    let mut reports = Vec::new();
+    for path in DataSource::discover(&BugBountySource, export) {
+        reports.extend(parse_bug_bounty_file(export, &path, mode)?);
+    }
+    reports.sort_by(|a, b| {
+        a.submitted_timestamp
+            .cmp(&b.submitted_timestamp)
+            .then_with(|| a.report_id.cmp(&b.report_id))
+    });
+    Ok(reports)
+}
+
+/// Bug bounty reports, for the [registry](crate::datasource::registry)
+pub struct BugBountySource;
+
+impl Skippable for BugBountySource {
+    fn path(&self) -> PathBuf {
+        ActivityTypes::BugBounty.path()
+    }
+
+    fn skippable(&self, export: &Export) -> bool {
+        ActivityTypes::BugBounty.skippable(export)
+    }
+}
+
+impl DataSource for BugBountySource {
+    type Record = BugBountyReport;
+
+    fn name(&self) -> &'static str {
+        "bug_bounty"
+    }
+
+    fn handles(&self, path: &Path) -> bool {
+        path.starts_with(ActivityTypes::BugBounty.path())
+            && path.extension().is_some_and(|ext| ext == "json")
+    }
+
+    fn parse(&self, export: &Export, path: &Path) -> Result<Vec<BugBountyReport>, MagicError> {
+        parse_bug_bounty_file(export, path, ParseMode::Lenient)
+    }
+
+    fn event(&self, _path: &Path, report: &BugBountyReport) -> Option<TimelineEvent> {
+        Some(TimelineEvent {
+            timestamp: report.submitted_timestamp,
+            source: "bug_bounty",
+            actor: None,
+            summary: format!("Reported {}: {}", report.report_id, report.title),
+        })
+    }
+
+    fn stats(&self, reports: &[BugBountyReport]) -> BTreeMap<String, usize> {
+        let mut stats = BTreeMap::from([("reports".to_string(), reports.len())]);
+        for report in reports {
+            let status = serde_json::to_value(report.status)
+                .ok()
+                .and_then(|status| status.as_str().map(str::to_string))
+                .unwrap_or_default();
+            *stats.entry(status).or_default() += 1;
+        }
+        stats
+    }
+}
+
+#[derive(Serialize)]
+struct ReportRow<'a> {
+    report_id: &'a str,
+    title: &'a str,
+    status: ReportStatus,
+    submitted: String,
+    updated: String,
+    paid: String,
+}
+
+fn format_paid(paid: &BTreeMap<&str, f64>) -> String {
+    paid.iter()
+        .map(|(currency, amount)| format!("{:.2} {}", amount, currency))
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+fn write_csv(reports: &[BugBountyReport], writer: impl Write) -> Result<(), MagicError> {
+    let mut writer = csv::Writer::from_writer(writer);
+    for report in reports {
+        writer
+            .serialize(ReportRow {
+                report_id: &report.report_id,
+                title: &report.title,
+                status: report.status,
+                submitted: report.submitted_timestamp.to_rfc3339(),
+                updated: report
+                    .updated_timestamp
+                    .map(|updated| updated.to_rfc3339())
+                    .unwrap_or_default(),
+                paid: format_paid(&report.paid()),
+            })
+            .map_err(|err| MagicError::Generic(err.to_string()))?;
+    }
+    writer
+        .flush()
+        .map_err(|err| MagicError::Generic(err.to_string()))
+}
+
+/// Lists the reports with totals, or writes them out in `format`
+pub fn bug_bounty_reports(
+    export: &Export,
+    mode: ParseMode,
+    format: Option<OutputFormat>,
+    output: Option<PathBuf>,
+) -> Result<(), MagicError> {
+    let reports = find_reports(export, mode)?;
+
+    if let Some(format) = format {
+        let writer: Box<dyn Write> = match &output {
+            Some(path) => Box::new(File::create(path).map_err(|err| {
+                MagicError::Generic(format!("Failed to create {}: {}", path.display(), err))
+            })?),
+            None => Box::new(std::io::stdout()),
+        };
+        return match format {
+            OutputFormat::Json => serde_json::to_writer_pretty(writer, &reports)
+                .map_err(|err| MagicError::Generic(err.to_string())),
+            OutputFormat::Csv => write_csv(&reports, writer),
+        };
+    }
+
+    let mut statuses: BTreeMap<ReportStatus, usize> = BTreeMap::new();
+    let mut paid: BTreeMap<&str, f64> = BTreeMap::new();
+    for report in reports.iter() {
+        *statuses.entry(report.status).or_default() += 1;
+        for (currency, amount) in report.paid() {
+            *paid.entry(currency).or_default() += amount;
+        }
+        println!(
+            "{} {} {:?}: {} {}",
+            report.submitted_timestamp.format("%Y-%m-%d"),
+            report.report_id,
+            report.status,
+            report.title,
+            format_paid(&report.paid())
+        );
+    }
+    println!("Total: {} reports", reports.len());
+    for (status, count) in statuses {
+        println!("  {:?}: {}", status, count);
+    }
+    if !paid.is_empty() {
+        println!("Paid: {}", format_paid(&paid));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_bug_bounty_file, ReportStatus};
+    use crate::activity::{ActivityTypes, ParseMode};
+    use crate::source::{DirectorySource, Export};
+    use crate::test_util::{temp_dir, write_file};
+    use crate::Skippable;
+
+    static TEST_FILE: &str = r#"{
+        "bug_bounty_reports_v2": [
+            {
+                "report_id": "1001",
+                "title": "XSS in the thing",
+                "status": "paid",
+                "submitted_timestamp": 1600000000,
+                "updated_timestamp": 1600100000,
+                "payouts": [
+                    {"amount": 500.0, "currency": "USD", "timestamp": 1600200000},
+                    {"amount": 250.0, "currency": "USD", "timestamp": 1600300000}
+                ]
+            },
+            {
+                "report_id": "1002",
+                "title": "Something new",
+                "status": "under_review",
+                "submitted_timestamp": 1610000000
+            }
+        ]
+    }"#;
+
+    #[test]
+    fn test_parse_modes() {
+        let temp = temp_dir();
+        let root = temp.path().to_path_buf();
+        let path = ActivityTypes::BugBounty.path().join("reports.json");
+        write_file(&root, &path, TEST_FILE);
+        let export = Export::from_source(DirectorySource::new(&root));
+
+        // the unknown status fails the whole file when strict
+        assert!(parse_bug_bounty_file(&export, &path, ParseMode::Strict).is_err());
+
+        let reports = parse_bug_bounty_file(&export, &path, ParseMode::Lenient).unwrap();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].status, ReportStatus::Paid);
+        assert_eq!(reports[0].paid()["USD"], 750.0);
+        assert_eq!(reports[0].submitted_timestamp.timestamp(), 1600000000);
+    }
+}
@@ -1,17 +1,41 @@
 //!
 //!  Messages related things
 //!
+use std::collections::BTreeMap;
 use std::fmt::Display;
 use std::fs::File;
-use std::io::BufReader;
+use std::io::{BufReader, Read};
 use std::net::IpAddr;
 use std::path::{Path, PathBuf};
 
 use rayon::prelude::*;
 use regex::Regex;
-use serde::Deserialize;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 
-use crate::{ActivityMessages, MagicError, BASE_PATH};
+use crate::activity::ActivityTypes;
+use crate::datasource::{DataSource, TimelineEvent};
+use crate::source::Export;
+use crate::{ActivityMessages, MagicError, Skippable};
+
+pub mod anonymize;
+pub mod calls;
+pub mod e2ee;
+pub mod html;
+pub mod id;
+pub mod ips;
+pub mod kind;
+pub mod links;
+pub mod merge;
+pub mod stream;
+pub mod threads;
+pub mod timeline;
+
+use id::MessageId;
+use kind::MessageKind;
+use merge::{message_file_copies, Deduplicator};
+use stream::MessageStream;
+use threads::{discover_threads, ThreadCategory, ThreadInfo};
 
 pub struct MessageBox {
     pub filepath: String,
@@ -19,34 +43,36 @@ pub struct MessageBox {
     pub participants: Vec<String>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, JsonSchema, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct MessageParticipant {
     pub name: String,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, JsonSchema, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct MessageMagicWord {
     pub magic_word: String,
 
+    #[schemars(with = "Option<i64>")]
     #[serde(
         with = "chrono::serde::ts_milliseconds_option",
-        default = "default_none_dt"
+        default = "default_none_dt",
+        skip_serializing_if = "Option::is_none"
     )]
     pub creation_timestamp_ms: Option<DateTime<Utc>>,
     // pub creation_timestamp_ms: u64,
     pub animation_emoji: String,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, JsonSchema, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct MessageJoinableMode {
     pub mode: usize,
     pub link: String,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, JsonSchema, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct MessageFileParser {
     pub participants: Vec<MessageParticipant>,
@@ -55,25 +81,43 @@ pub struct MessageFileParser {
     pub is_still_participant: bool,
     pub thread_path: String,
     pub magic_words: Vec<MessageMagicWord>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub image: Option<MessagePhoto>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub joinable_mode: Option<MessageJoinableMode>,
+    /// `Regular` or `RegularGroup`, only in older exports
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thread_type: Option<String>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, JsonSchema, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct MessageShare {
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub link: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub share_text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub is_geoblocked_for_viewer: Option<bool>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, JsonSchema, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct MessageMedia {
+    #[serde(skip)]
+    pub id: MessageId,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub uri: Option<String>,
-    #[serde(with = "chrono::serde::ts_seconds_option", default = "default_none_dt")]
+    #[schemars(with = "Option<i64>")]
+    #[serde(
+        with = "chrono::serde::ts_seconds_option",
+        default = "default_none_dt",
+        skip_serializing_if = "Option::is_none"
+    )]
     pub creation_timestamp: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub share_text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub is_geoblocked_for_viewer: Option<bool>,
 }
 
@@ -83,243 +127,492 @@ fn default_none_dt() -> Option<DateTime<Utc>> {
     None
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, JsonSchema, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct MessagePhoto {
+    #[serde(skip)]
+    pub id: MessageId,
     pub uri: String,
-    #[serde(with = "chrono::serde::ts_seconds_option", default = "default_none_dt")]
+    #[schemars(with = "Option<i64>")]
+    #[serde(
+        with = "chrono::serde::ts_seconds_option",
+        default = "default_none_dt",
+        skip_serializing_if = "Option::is_none"
+    )]
     pub creation_timestamp: Option<DateTime<Utc>>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, JsonSchema, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct MessageReaction {
     pub reaction: String,
     pub actor: String,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, JsonSchema, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct MessageVideo {
+    #[serde(skip)]
+    pub id: MessageId,
     pub uri: String,
+    #[schemars(with = "i64")]
     #[serde(with = "chrono::serde::ts_seconds")]
     pub creation_timestamp: DateTime<Utc>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, JsonSchema, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct MessageAiSticker {
     pub input: String,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, JsonSchema, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct MessageSticker {
+    #[serde(skip)]
+    pub id: MessageId,
     pub uri: String,
     pub ai_stickers: Vec<MessageAiSticker>,
 }
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone)]
 #[serde(deny_unknown_fields)]
 pub struct MessageFile {
+    #[serde(skip)]
+    pub id: MessageId,
     pub uri: String,
-    #[serde(with = "chrono::serde::ts_seconds_option", default = "default_none_dt")]
+    #[schemars(with = "Option<i64>")]
+    #[serde(
+        with = "chrono::serde::ts_seconds_option",
+        default = "default_none_dt",
+        skip_serializing_if = "Option::is_none"
+    )]
     pub creation_timestamp: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub title: Option<String>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, JsonSchema, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct Message {
+    /// Derived from the message when it's loaded, see [MessageId::for_message]
+    #[serde(skip)]
+    pub id: MessageId,
     pub sender_name: String,
-    pub is_unsent: Option<bool>,
     pub timestamp_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub content: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub share: Option<MessageShare>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub videos: Option<Vec<MessageVideo>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub reactions: Option<Vec<MessageReaction>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub photos: Option<Vec<MessagePhoto>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub gifs: Option<Vec<MessagePhoto>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub is_unsent: Option<bool>,
     pub is_geoblocked_for_viewer: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub call_duration: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub sticker: Option<MessageSticker>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub files: Option<Vec<MessageFile>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub audio_files: Option<Vec<MessageMedia>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub ip: Option<IpAddr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub missed: Option<bool>,
 }
 
+impl Message {
+    /// The URIs of everything attached to the message, in the order they're listed
+    pub fn attachment_uris(&self) -> Vec<&str> {
+        let mut uris = Vec::new();
+        uris.extend(self.photos.iter().flatten().map(|p| p.uri.as_str()));
+        uris.extend(self.gifs.iter().flatten().map(|g| g.uri.as_str()));
+        uris.extend(self.videos.iter().flatten().map(|v| v.uri.as_str()));
+        uris.extend(self.sticker.iter().map(|s| s.uri.as_str()));
+        uris.extend(self.files.iter().flatten().map(|f| f.uri.as_str()));
+        uris.extend(
+            self.audio_files
+                .iter()
+                .flatten()
+                .filter_map(|a| a.uri.as_deref()),
+        );
+        uris
+    }
+
+    /// Set the IDs on the message and its attachments, `thread` is the thread's path
+    pub fn assign_ids(&mut self, thread: &str) {
+        let id = MessageId::for_message(thread, self);
+        self.id = id;
+        for photo in self.photos.iter_mut().flatten() {
+            photo.id = MessageId::for_attachment(&id, &photo.uri);
+        }
+        for gif in self.gifs.iter_mut().flatten() {
+            gif.id = MessageId::for_attachment(&id, &gif.uri);
+        }
+        for video in self.videos.iter_mut().flatten() {
+            video.id = MessageId::for_attachment(&id, &video.uri);
+        }
+        if let Some(sticker) = self.sticker.as_mut() {
+            sticker.id = MessageId::for_attachment(&id, &sticker.uri);
+        }
+        for file in self.files.iter_mut().flatten() {
+            file.id = MessageId::for_attachment(&id, &file.uri);
+        }
+        for audio in self.audio_files.iter_mut().flatten() {
+            audio.id = MessageId::for_attachment(&id, audio.uri.as_deref().unwrap_or_default());
+        }
+    }
+}
+
 impl TryFrom<&PathBuf> for MessageFileParser {
     type Error = MagicError;
     fn try_from(path: &PathBuf) -> Result<Self, MagicError> {
-        let file = File::open(path).unwrap();
-        let reader = BufReader::new(file);
-        let data: Result<MessageFileParser, MagicError> =
-            serde_json::from_reader(reader).map_err(|err| MagicError::Generic(err.to_string()));
-        data
-    }
-}
-
-fn is_message_file(path: &Path) -> bool {
-    let valid_filename = regex::Regex::new(r"\/message_[\d]+\.json$").unwrap();
-    valid_filename.is_match(path.to_str().unwrap())
-}
-
-pub fn select_message_folder() -> PathBuf {
-    let mut folders = Vec::new();
-
-    for entry in glob::glob(&format!(
-        "{}/your_activity_across_facebook/messages/**/*.json",
-        BASE_PATH
-    ))
-    .expect("Failed to read glob pattern")
-    {
-        match entry {
-            Ok(path) => {
-                if is_message_file(&path) {
-                    let parent_folder = path.clone();
-                    let parent_folder = parent_folder.parent().unwrap();
-                    if parent_folder.is_dir() {
-                        if !folders.contains(&parent_folder.to_path_buf()) {
-                            folders.push(parent_folder.to_owned());
-                        }
-                    } else {
-                        panic!("Uh, {:?} is a parent but is not a dir?", parent_folder);
-                    }
-                }
-            }
-            Err(e) => println!("{:?}", e),
+        let file = File::open(path)
+            .map_err(|err| MagicError::Generic(format!("{}: {}", path.display(), err)))?;
+        Self::from_reader(BufReader::new(file), path)
+    }
+}
+
+impl MessageFileParser {
+    /// Whether it's a group chat. Older exports say in `thread_type`, newer ones only give
+    /// groups a `joinable_mode`.
+    pub fn is_group(&self) -> bool {
+        match (&self.thread_type, &self.joinable_mode) {
+            (Some(thread_type), _) => thread_type == "RegularGroup",
+            (None, joinable_mode) => joinable_mode.is_some(),
         }
     }
-    println!("Found {} folders", folders.len());
-    let folders_display: Vec<String> = folders.iter().map(|f| f.display().to_string()).collect();
+
+    /// Parse a message file from wherever the export's stored
+    pub fn from_export(export: &Export, path: &Path) -> Result<Self, MagicError> {
+        Self::from_reader(export.open(path)?, path)
+    }
+
+    /// Parse a message file in any of the supported formats, `path` is where it's from
+    pub fn from_reader<R: Read>(reader: R, path: &Path) -> Result<Self, MagicError> {
+        let mut messages = Vec::new();
+        let mut parsed = stream::stream_message_reader(reader, path, |message| {
+            messages.push(message);
+            Ok(())
+        })?;
+        parsed.messages = messages;
+        Ok(parsed)
+    }
+}
+
+/// The `inbox/bob_123` part of a message file's path, which is what the JSON calls `thread_path`
+pub(crate) fn thread_path_from_file(path: &Path) -> String {
+    let parent = path.parent().unwrap_or(Path::new(""));
+    let components: Vec<String> = parent
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().to_string())
+        .collect();
+    match components.iter().rposition(|c| c == "messages") {
+        Some(index) => components[index + 1..].join("/"),
+        None => components.join("/"),
+    }
+}
+
+pub(crate) fn is_message_file(path: &Path) -> bool {
+    let valid_filename = regex::Regex::new(r"^message_[\d]+\.(json|html)$").unwrap();
+    path.file_name()
+        .and_then(|filename| filename.to_str())
+        .is_some_and(|filename| valid_filename.is_match(filename))
+}
+
+/// Ask which thread to use, only showing threads in `categories` if any are given
+pub fn select_message_folder(export: &Export, categories: &[ThreadCategory]) -> PathBuf {
+    let threads = discover_threads(export, categories);
+    println!("Found {} folders", threads.len());
+    let threads_display: Vec<String> = threads.iter().map(|t| t.to_string()).collect();
 
     let res = dialoguer::FuzzySelect::new()
-        .items(&folders_display)
+        .items(&threads_display)
         .with_prompt("Select a folder")
         .interact()
         .unwrap();
-    folders[res].clone()
+    threads[res].folder.clone()
 }
 
-pub fn get_all_messages(folder: &Path) -> Result<Vec<Message>, MagicError> {
-    let mut parsed_filecount = 0;
-    let mut messages = Vec::new();
-    for entry in
-        glob::glob(&format!("{}/**/*.json", folder.display())).expect("Failed to read glob pattern")
-    {
-        match entry {
-            Ok(path) => {
-                if !is_message_file(&path) {
-                    continue;
-                } else {
-                    // eprintln!("Trying {}", path.display());
-                    let parsed = MessageFileParser::try_from(&path)?;
-                    messages.extend(parsed.messages);
-                    parsed_filecount += 1;
-                }
-            }
-            Err(e) => println!("{:?}", e),
+/// Use the folder from the command line if there is one, otherwise ask
+fn target_folder(export: &Export, msg: &ActivityMessages) -> PathBuf {
+    match &msg.target_folder {
+        Some(folder) => export.relative_path(Path::new(folder)),
+        None => select_message_folder(export, &msg.category),
+    }
+}
+
+/// The threads to look at: the ones under the folder given on the command line if there is one,
+/// otherwise every thread in the categories. It's an error if the folder has no threads in it.
+fn selected_threads(
+    export: &Export,
+    msg: &ActivityMessages,
+) -> Result<Vec<ThreadInfo>, MagicError> {
+    let mut threads = discover_threads(export, &msg.category);
+    if msg.target_folder.is_some() {
+        let folder = target_folder(export, msg);
+        threads.retain(|thread| thread.folder.starts_with(&folder));
+        if threads.is_empty() {
+            return Err(MagicError::Generic(match msg.category.is_empty() {
+                true => format!("No threads in {}", folder.display()),
+                false => format!("No threads in {} in the categories given", folder.display()),
+            }));
         }
     }
-    println!(
-        "Parsed {} files, found {} messages",
-        parsed_filecount,
-        messages.len()
-    );
-    Ok(messages)
+    Ok(threads)
 }
 
-pub fn reorg_videos(msg: ActivityMessages) -> Result<(), MagicError> {
-    let folder = match msg.target_folder {
-        Some(folder) => PathBuf::from(folder),
-        None => select_message_folder(),
-    };
+pub fn list_threads(export: &Export, msg: ActivityMessages) -> Result<(), MagicError> {
+    for thread in discover_threads(export, &msg.category) {
+        println!("{} {}", thread.folder.display(), thread);
+    }
+    Ok(())
+}
 
-    let messages = get_all_messages(&folder)?;
-    let username = folder.iter().last().unwrap().to_str().unwrap();
+/// Pulls the number out of `message_<n>.json` so files sort in the order Facebook wrote them
+fn message_file_number(path: &Path) -> usize {
+    path.file_stem()
+        .and_then(|stem| stem.to_str())
+        .and_then(|stem| stem.strip_prefix("message_"))
+        .and_then(|num| num.parse().ok())
+        .unwrap_or(usize::MAX)
+}
 
-    messages.par_iter().for_each(|msg| {
-        // println!("{:?}", msg);
-        if let Some(videos) = &msg.videos {
-            for video in videos {
-                // println!("Photo: {:?}", photo);
+/// Finds all the message files under a folder, sorted by thread then file number so the
+/// results are the same every run.
+pub fn find_message_files(export: &Export, folder: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = export
+        .files(folder)
+        .into_iter()
+        .filter(|path| is_message_file(path))
+        .collect();
+    files.sort_by(|a, b| {
+        a.parent()
+            .cmp(&b.parent())
+            .then_with(|| message_file_number(a).cmp(&message_file_number(b)))
+            .then_with(|| a.cmp(b))
+    });
+    files
+}
 
-                let filepath = PathBuf::from(format!("{}/{}", BASE_PATH, video.uri));
+/// Messages, for the [registry](crate::datasource::registry)
+pub struct MessagesSource;
 
-                let datepath = PathBuf::from(format!(
-                    "output/{}/{}",
-                    username,
-                    video.creation_timestamp.format("%Y/%m")
-                ));
-                if !datepath.exists() {
-                    std::fs::create_dir_all(&datepath).unwrap();
-                }
-                let timestamp_filebit = video.creation_timestamp.format("%Y-%m-%d-%H-%M-%S");
+impl Skippable for MessagesSource {
+    fn path(&self) -> PathBuf {
+        ActivityTypes::Messages.path()
+    }
+
+    fn skippable(&self, export: &Export) -> bool {
+        ActivityTypes::Messages.skippable(export)
+    }
+}
+
+/// A message for `export records`, with what's needed to tie it back to its thread and to
+/// the IDs in other output
+#[derive(Serialize, Debug)]
+pub struct MessageRecord {
+    /// eg `inbox/bobsmith_1`
+    pub thread: String,
+    pub id: MessageId,
+    #[serde(flatten)]
+    pub message: Message,
+}
+
+impl DataSource for MessagesSource {
+    type Record = MessageRecord;
+
+    fn name(&self) -> &'static str {
+        "messages"
+    }
+
+    fn handles(&self, path: &Path) -> bool {
+        is_message_file(path)
+    }
+
+    /// The first file of each thread, [DataSource::parse] reads the rest of the thread with it
+    /// so messages can be deduplicated across merged exports
+    fn discover(&self, export: &Export) -> Vec<PathBuf> {
+        let mut files = find_message_files(export, &ActivityTypes::Messages.path());
+        files.dedup_by(|a, b| a.parent() == b.parent());
+        files
+    }
+
+    fn parse(&self, export: &Export, path: &Path) -> Result<Vec<MessageRecord>, MagicError> {
+        let thread = thread_path_from_file(path);
+        let folder = path.parent().unwrap_or(Path::new(""));
+        let (_, messages) = read_messages(export, folder)?;
+        Ok(messages
+            .into_iter()
+            .map(|message| MessageRecord {
+                thread: thread.clone(),
+                id: message.id,
+                message,
+            })
+            .collect())
+    }
+
+    fn event(&self, _path: &Path, record: &MessageRecord) -> Option<TimelineEvent> {
+        let message = &record.message;
+        let kind = message.kind();
+        let what = match (&kind, message.content.as_deref()) {
+            (MessageKind::Text | MessageKind::System(_), Some(content)) => content.to_string(),
+            _ => kind.name().to_string(),
+        };
+        Some(TimelineEvent {
+            timestamp: DateTime::<Utc>::from_timestamp_millis(message.timestamp_ms as i64)?,
+            source: "messages",
+            actor: Some(message.sender_name.clone()),
+            summary: format!("{}: {}", record.thread, what),
+        })
+    }
 
-                let new_filename = format!(
-                    "{}/{}-{}",
-                    datepath.display(),
-                    timestamp_filebit,
-                    filepath.file_name().unwrap().to_str().unwrap()
+    fn stats(&self, records: &[MessageRecord]) -> BTreeMap<String, usize> {
+        let mut stats = BTreeMap::from([("messages".to_string(), records.len())]);
+        for record in records {
+            let kind = record.message.kind();
+            *stats.entry(kind.name().to_string()).or_default() += 1;
+        }
+        stats
+    }
+}
+
+/// How many files to parse at once per thread in the pool, this caps how many parsed files
+/// are held in memory before their messages are moved into the result.
+static PARSE_BATCH_PER_THREAD: usize = 2;
+
+/// Loads every message under a folder. If older exports have been merged in, messages only in
+/// those are included and the copies of ones in more than one export are dropped.
+pub fn get_all_messages(export: &Export, folder: &Path) -> Result<Vec<Message>, MagicError> {
+    let (files, messages) = read_messages(export, folder)?;
+    println!("Parsed {} files, found {} messages", files, messages.len());
+    Ok(messages)
+}
+
+/// [get_all_messages] without the output, along with how many files were read
+fn read_messages(export: &Export, folder: &Path) -> Result<(usize, Vec<Message>), MagicError> {
+    let files = message_file_copies(export, find_message_files(export, folder));
+    let batch_size = rayon::current_num_threads() * PARSE_BATCH_PER_THREAD;
+
+    let mut dedup = Deduplicator::default();
+    let mut messages = Vec::new();
+    for batch in files.chunks(batch_size.max(1)) {
+        let parsed = batch
+            .par_iter()
+            .map(|(path, source)| MessageFileParser::from_export(source, path))
+            .collect::<Result<Vec<MessageFileParser>, MagicError>>()?;
+        for (file, (path, _)) in parsed.into_iter().zip(batch) {
+            if export.is_merged() {
+                let thread = thread_path_from_file(path);
+                messages.extend(
+                    file.messages
+                        .into_iter()
+                        .filter(|message| dedup.is_new(&thread, message)),
                 );
-                println!("new_filename {}", new_filename);
-                // copy the file
-                std::fs::copy(&filepath, &new_filename).unwrap();
+            } else {
+                messages.extend(file.messages);
             }
         }
-    });
+    }
+    Ok((files.len(), messages))
+}
 
-    Ok(())
+pub fn reorg_videos(export: &Export, msg: ActivityMessages) -> Result<(), MagicError> {
+    let folder = target_folder(export, &msg);
+
+    let username = folder.iter().next_back().unwrap().to_str().unwrap();
+
+    MessageStream::new(export, &folder)
+        .par_bridge()
+        .try_for_each(|msg| {
+            let msg = msg?;
+            if let Some(videos) = &msg.videos {
+                for video in videos {
+                    // println!("Photo: {:?}", photo);
+
+                    let filepath = PathBuf::from(&video.uri);
+
+                    let datepath = PathBuf::from(format!(
+                        "output/{}/{}",
+                        username,
+                        video.creation_timestamp.format("%Y/%m")
+                    ));
+                    if !datepath.exists() {
+                        std::fs::create_dir_all(&datepath).unwrap();
+                    }
+                    let timestamp_filebit = video.creation_timestamp.format("%Y-%m-%d-%H-%M-%S");
+
+                    let new_filename = format!(
+                        "{}/{}-{}",
+                        datepath.display(),
+                        timestamp_filebit,
+                        filepath.file_name().unwrap().to_str().unwrap()
+                    );
+                    println!("new_filename {}", new_filename);
+                    // copy the file
+                    export.copy(&filepath, Path::new(&new_filename))?;
+                }
+            }
+            Ok(())
+        })
 }
 
-pub fn reorg_images(msg: ActivityMessages) -> Result<(), MagicError> {
+pub fn reorg_images(export: &Export, msg: ActivityMessages) -> Result<(), MagicError> {
     // println!("Messages: {:?}", msg);
-    let folder = match msg.target_folder {
-        Some(folder) => PathBuf::from(folder),
-        None => select_message_folder(),
-    };
+    let folder = target_folder(export, &msg);
     println!("Target folder: {}", folder.display());
-    let messages = get_all_messages(&folder)?;
-
-    let username = folder.iter().last().unwrap().to_str().unwrap();
-
-    messages.par_iter().for_each(|msg| {
-        // println!("{:?}", msg);
-        if let Some(photos) = &msg.photos {
-            for photo in photos {
-                // println!("Photo: {:?}", photo);
-                match photo.creation_timestamp {
-                    Some(timestamp) => {
-                        let filepath = PathBuf::from(format!("{}/{}", BASE_PATH, photo.uri));
-
-                        let datepath = PathBuf::from(format!(
-                            "output/{}/{}",
-                            username,
-                            timestamp.format("%Y/%m")
-                        ));
-                        if !datepath.exists() {
-                            std::fs::create_dir_all(&datepath).unwrap();
+
+    let username = folder.iter().next_back().unwrap().to_str().unwrap();
+
+    MessageStream::new(export, &folder)
+        .par_bridge()
+        .try_for_each(|msg| {
+            let msg = msg?;
+            if let Some(photos) = &msg.photos {
+                for photo in photos {
+                    // println!("Photo: {:?}", photo);
+                    // the HTML export doesn't have photo timestamps, fall back to the message's
+                    let timestamp = photo.creation_timestamp.or_else(|| {
+                        DateTime::<Utc>::from_timestamp_millis(msg.timestamp_ms as i64)
+                    });
+                    match timestamp {
+                        Some(timestamp) => {
+                            let filepath = PathBuf::from(&photo.uri);
+
+                            let datepath = PathBuf::from(format!(
+                                "output/{}/{}",
+                                username,
+                                timestamp.format("%Y/%m")
+                            ));
+                            if !datepath.exists() {
+                                std::fs::create_dir_all(&datepath).unwrap();
+                            }
+                            let timestamp_filebit = timestamp.format("%Y-%m-%d-%H-%M-%S");
+
+                            let new_filename = format!(
+                                "{}/{}-{}",
+                                datepath.display(),
+                                timestamp_filebit,
+                                filepath.file_name().unwrap().to_str().unwrap()
+                            );
+                            println!("new_filename {}", new_filename);
+                            // copy the file
+                            export.copy(&filepath, Path::new(&new_filename))?;
+                            // println!("{}", new_filenamehoto);
                         }
-                        let timestamp_filebit = timestamp.format("%Y-%m-%d-%H-%M-%S");
-
-                        let new_filename = format!(
-                            "{}/{}-{}",
-                            datepath.display(),
-                            timestamp_filebit,
-                            filepath.file_name().unwrap().to_str().unwrap()
-                        );
-                        println!("new_filename {}", new_filename);
-                        // copy the file
-                        std::fs::copy(&filepath, &new_filename).unwrap();
-                        // println!("{}", new_filenamehoto);
+                        None => todo!("handle {:?}", photo),
                     }
-                    None => todo!("handle {:?}", photo),
                 }
             }
-        }
-    });
-
-    Ok(())
+            Ok(())
+        })
 }
 
 #[derive(Default, Debug)]
@@ -384,13 +677,15 @@ impl Display for SearchMenu {
     }
 }
 
-pub fn search_messages(path: Option<PathBuf>) -> Result<(), MagicError> {
+pub fn search_messages(
+    export: &Export,
+    path: Option<PathBuf>,
+    categories: &[ThreadCategory],
+) -> Result<(), MagicError> {
     let path = match path {
-        Some(path) => path,
-        None => select_message_folder(),
+        Some(path) => export.relative_path(&path),
+        None => select_message_folder(export, categories),
     };
-    println!("Loading messages from target folder: {}", path.display());
-    let messages = get_all_messages(&path)?;
 
     let mut searchterms = SearchTerms::default();
     loop {
@@ -494,16 +789,18 @@ pub fn search_messages(path: Option<PathBuf>) -> Result<(), MagicError> {
     }
     let regex = searchterms.regex.clone();
 
-    messages.into_iter().for_each(|msg| {
+    println!("Searching messages in target folder: {}", path.display());
+    for msg in MessageStream::new(export, &path) {
+        let msg = msg?;
         if let Some(earliest) = searchterms.earliest {
             if msg.timestamp_ms < earliest.timestamp_millis() as u64 {
-                return;
+                continue;
             }
         }
 
         if let Some(latest) = searchterms.latest {
             if msg.timestamp_ms > latest.timestamp_millis() as u64 {
-                return;
+                continue;
             }
         }
 
@@ -516,46 +813,113 @@ pub fn search_messages(path: Option<PathBuf>) -> Result<(), MagicError> {
                 .unwrap_or(&"".to_string())
                 .contains(string)
             {
-                return;
+                continue;
             }
         }
 
         // filter on regex
         if let Some(regex) = &regex {
             if !regex.is_match(&msg.content.clone().unwrap_or("".to_string())) {
-                return;
+                continue;
             }
         }
         println!("{:?}", &msg)
-    });
+    }
     Ok(())
 }
 
-pub fn list_files(msg: ActivityMessages) -> Result<(), MagicError> {
-    let folder = match msg.target_folder {
-        Some(folder) => PathBuf::from(folder),
-        None => select_message_folder(),
-    };
+pub fn list_files(export: &Export, msg: ActivityMessages) -> Result<(), MagicError> {
+    let folder = target_folder(export, &msg);
     println!("Target folder: {}", folder.display());
-    let messages = get_all_messages(&folder)?;
-
-    // let username = folder.iter().last().unwrap().to_str().unwrap();
 
-    messages.par_iter().for_each(|msg| {
-        if let Some(files) = msg.files.clone() {
+    for msg in MessageStream::new(export, &folder) {
+        if let Some(files) = msg?.files {
             for file in files {
                 println!("File: {:?}", file);
             }
         }
-    });
+    }
+    Ok(())
+}
+
+#[derive(Default, Debug)]
+struct SenderStats {
+    messages: usize,
+    photos: usize,
+    videos: usize,
+    files: usize,
+    system: usize,
+    first_ms: Option<u64>,
+    last_ms: Option<u64>,
+}
+
+/// Count up messages and attachments per sender, streaming so it works on any size of thread
+pub fn message_stats(export: &Export, msg: ActivityMessages) -> Result<(), MagicError> {
+    let folder = target_folder(export, &msg);
+    println!("Target folder: {}", folder.display());
+
+    let mut senders: BTreeMap<String, SenderStats> = BTreeMap::new();
+    for msg in MessageStream::new(export, &folder) {
+        let msg = msg?;
+        let is_system = matches!(msg.kind(), MessageKind::System(_));
+        let stats = senders.entry(msg.sender_name).or_default();
+        stats.messages += 1;
+        stats.system += is_system as usize;
+        stats.photos += msg.photos.map(|p| p.len()).unwrap_or(0);
+        stats.videos += msg.videos.map(|v| v.len()).unwrap_or(0);
+        stats.files += msg.files.map(|f| f.len()).unwrap_or(0);
+        stats.first_ms = Some(
+            stats
+                .first_ms
+                .map_or(msg.timestamp_ms, |t| t.min(msg.timestamp_ms)),
+        );
+        stats.last_ms = Some(
+            stats
+                .last_ms
+                .map_or(msg.timestamp_ms, |t| t.max(msg.timestamp_ms)),
+        );
+    }
+
+    let format_ms = |ms: Option<u64>| {
+        ms.and_then(|ms| DateTime::<Utc>::from_timestamp_millis(ms as i64))
+            .map(|dt| dt.to_rfc3339())
+            .unwrap_or_default()
+    };
+    for (sender, stats) in senders.iter() {
+        println!(
+            "{}: {} messages, {} photos, {} videos, {} files, {} group notices, {} - {}",
+            sender,
+            stats.messages,
+            stats.photos,
+            stats.videos,
+            stats.files,
+            stats.system,
+            format_ms(stats.first_ms),
+            format_ms(stats.last_ms),
+        );
+    }
+    println!(
+        "Total: {} messages from {} senders",
+        senders.values().map(|s| s.messages).sum::<usize>(),
+        senders.len()
+    );
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
+    use std::path::Path;
+
+    use proptest::prelude::*;
+    use serde_json::{json, Map, Value};
+
     use crate::BASE_PATH;
 
-    use super::{is_message_file, MessageFileParser};
+    use super::{is_message_file, selected_threads, MessageFileParser};
+    use crate::activity::messages::threads::ThreadCategory;
+    use crate::source::{DirectorySource, Export};
+    use crate::test_util::{message_file, temp_dir, write_file};
+    use crate::{ActivityMessages, ActivityMessagesSubCommand};
 
     #[test]
     fn test_messagefileparser() {
@@ -582,4 +946,193 @@ mod tests {
         }
         println!("Parsed {} files", parsed_filecount);
     }
+
+    fn media_strategy(seconds: bool) -> impl Strategy<Value = Value> {
+        (
+            "[a-z/_]{1,20}\\.(jpg|mp4|gif)",
+            proptest::option::of(0i64..4_000_000_000),
+        )
+            .prop_map(move |(uri, timestamp)| {
+                let mut media = json!({ "uri": uri });
+                match (timestamp, seconds) {
+                    (Some(timestamp), _) => media["creation_timestamp"] = json!(timestamp),
+                    // videos always have one
+                    (None, false) => media["creation_timestamp"] = json!(0),
+                    (None, true) => {}
+                }
+                media
+            })
+    }
+
+    fn message_strategy() -> impl Strategy<Value = Value> {
+        let text = || proptest::option::of("\\PC{0,40}");
+        let media = |seconds| {
+            proptest::option::of(proptest::collection::vec(media_strategy(seconds), 1..3))
+        };
+        (
+            (
+                "\\PC{1,20}",
+                0u64..4_000_000_000_000,
+                text(),
+                proptest::option::of(any::<bool>()),
+                proptest::option::of((text(), text(), proptest::option::of(any::<bool>()))),
+                media(false),
+                media(true),
+                media(true),
+            ),
+            (
+                proptest::option::of(proptest::collection::vec(("\\PC{1,4}", "\\PC{1,20}"), 1..3)),
+                proptest::option::of(0u64..100_000),
+                proptest::option::of(any::<bool>()),
+                proptest::option::of(any::<[u8; 4]>()),
+                proptest::option::of(("[a-z/_]{1,20}\\.png", proptest::collection::vec("\\PC{0,10}", 0..2))),
+                media(true),
+                proptest::option::of(("[a-z/_]{1,20}\\.pdf", text())),
+            ),
+        )
+            .prop_map(
+                |(
+                    (sender, timestamp_ms, content, is_unsent, share, videos, photos, gifs),
+                    (reactions, call_duration, missed, ip, sticker, audio_files, file),
+                )| {
+                    // in the order Facebook writes them, which is the order of the fields
+                    let mut message = Map::new();
+                    let mut set = |key: &str, value: Option<Value>| {
+                        if let Some(value) = value {
+                            message.insert(key.to_string(), value);
+                        }
+                    };
+                    set("sender_name", Some(json!(sender)));
+                    set("timestamp_ms", Some(json!(timestamp_ms)));
+                    set("content", content.map(Value::from));
+                    set(
+                        "share",
+                        share.map(|(link, share_text, geoblocked)| {
+                            let mut share = json!({});
+                            if let Some(link) = link {
+                                share["link"] = json!(link);
+                            }
+                            if let Some(share_text) = share_text {
+                                share["share_text"] = json!(share_text);
+                            }
+                            if let Some(geoblocked) = geoblocked {
+                                share["is_geoblocked_for_viewer"] = json!(geoblocked);
+                            }
+                            share
+                        }),
+                    );
+                    set("videos", videos.map(Value::from));
+                    set(
+                        "reactions",
+                        reactions.map(|reactions| {
+                            reactions
+                                .into_iter()
+                                .map(|(reaction, actor)| json!({"reaction": reaction, "actor": actor}))
+                                .collect()
+                        }),
+                    );
+                    set("photos", photos.map(Value::from));
+                    set("gifs", gifs.map(Value::from));
+                    set("is_unsent", is_unsent.map(Value::from));
+                    set("is_geoblocked_for_viewer", Some(json!(false)));
+                    set("call_duration", call_duration.map(Value::from));
+                    set(
+                        "sticker",
+                        sticker.map(|(uri, inputs)| {
+                            let ai_stickers: Vec<Value> =
+                                inputs.into_iter().map(|input| json!({ "input": input })).collect();
+                            json!({"uri": uri, "ai_stickers": ai_stickers})
+                        }),
+                    );
+                    set(
+                        "files",
+                        file.map(|(uri, title)| {
+                            let mut file = json!({ "uri": uri });
+                            if let Some(title) = title {
+                                file["title"] = json!(title);
+                            }
+                            json!([file])
+                        }),
+                    );
+                    set("audio_files", audio_files.map(Value::from));
+                    set("ip", ip.map(|ip| json!(std::net::Ipv4Addr::from(ip).to_string())));
+                    set("missed", missed.map(Value::from));
+                    Value::Object(message)
+                },
+            )
+    }
+
+    proptest! {
+        #[test]
+        fn test_message_round_trip(
+            messages in proptest::collection::vec(message_strategy(), 0..5),
+            magic_word_timestamp in proptest::option::of(0i64..4_000_000_000_000),
+            joinable in any::<bool>(),
+        ) {
+            let mut magic_word = json!({"magic_word": "congrats"});
+            if let Some(timestamp) = magic_word_timestamp {
+                magic_word["creation_timestamp_ms"] = json!(timestamp);
+            }
+            magic_word["animation_emoji"] = json!("x");
+            let mut file = json!({
+                "participants": [{"name": "Alice"}, {"name": "Bob"}],
+                "messages": messages,
+                "title": "Bob",
+                "is_still_participant": true,
+                "thread_path": "inbox/bob_1",
+                "magic_words": [magic_word],
+            });
+            if joinable {
+                file["image"] = json!({"uri": "photos/group.jpg", "creation_timestamp": 1});
+                file["joinable_mode"] = json!({"mode": 1, "link": "https://m.me/j/x"});
+            }
+
+            // compared as text, Value equality doesn't care about the order of the fields
+            let original = serde_json::to_string(&file).unwrap();
+            let parsed: MessageFileParser = serde_json::from_str(&original).unwrap();
+            let serialized = serde_json::to_string(&parsed).unwrap();
+            prop_assert_eq!(&serialized, &original);
+            let reparsed: MessageFileParser = serde_json::from_str(&serialized).unwrap();
+            prop_assert_eq!(serde_json::to_string(&reparsed).unwrap(), original);
+        }
+    }
+
+    #[test]
+    fn test_selected_threads() {
+        let temp = temp_dir();
+        let root = temp.path().to_path_buf();
+        for thread in ["inbox/bob_1", "inbox/carol_2", "archived_threads/dave_3"] {
+            let file = Path::new("your_activity_across_facebook/messages")
+                .join(thread)
+                .join("message_1.json");
+            write_file(&root, file, message_file(thread, json!([])).to_string());
+        }
+        let export = Export::from_source(DirectorySource::new(&root));
+        let msg = |target_folder: &str, category: Vec<ThreadCategory>| ActivityMessages {
+            command: ActivityMessagesSubCommand::ListThreads,
+            target_folder: Some(target_folder.to_string()),
+            category,
+        };
+
+        let threads = selected_threads(
+            &export,
+            &msg("your_activity_across_facebook/messages/inbox", vec![]),
+        )
+        .unwrap();
+        assert_eq!(threads.len(), 2);
+        let threads = selected_threads(
+            &export,
+            &msg("your_activity_across_facebook/messages/inbox/bob_1", vec![]),
+        )
+        .unwrap();
+        assert_eq!(threads.len(), 1);
+        assert!(selected_threads(
+            &export,
+            &msg(
+                "your_activity_across_facebook/messages/archived_threads",
+                vec![ThreadCategory::Inbox]
+            )
+        )
+        .is_err());
+    }
 }
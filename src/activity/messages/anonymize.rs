@@ -0,0 +1,571 @@
+//!
+//! Rewriting a thread so it can be shared, eg as a test fixture or with a bug report
+//!
+//! Names are swapped for pseudonyms, consistently across the thread. Text keeps its length and
+//! shape but every letter and digit is scrambled, timestamps are shifted, and attachments are
+//! replaced with tiny placeholders. System notices keep their wording so they still parse.
+//!
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+use serde_json::{Map, Value};
+
+use super::e2ee::MessageFileFormat;
+use super::kind::SystemEvent;
+use super::{find_message_files, target_folder, MessageFileParser};
+use crate::activity::ActivityTypes;
+use crate::source::Export;
+use crate::{ActivityMessages, MagicError, Skippable};
+
+/// A 1x1 transparent GIF
+static PLACEHOLDER_GIF: &[u8] = &[
+    0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0xff, 0xff, 0xff, 0x21, 0xf9, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00, 0x2c, 0x00, 0x00, 0x00, 0x00,
+    0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x02, 0x44, 0x01, 0x00, 0x3b,
+];
+
+/// A 1x1 black PNG
+static PLACEHOLDER_PNG: &[u8] = &[
+    0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x49, 0x48, 0x44, 0x52,
+    0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x08, 0x00, 0x00, 0x00, 0x00, 0x3a, 0x7e, 0x9b,
+    0x55, 0x00, 0x00, 0x00, 0x0a, 0x49, 0x44, 0x41, 0x54, 0x78, 0x9c, 0x63, 0x60, 0x00, 0x00, 0x00,
+    0x02, 0x00, 0x01, 0x48, 0xaf, 0xa4, 0x71, 0x00, 0x00, 0x00, 0x00, 0x49, 0x45, 0x4e, 0x44, 0xae,
+    0x42, 0x60, 0x82,
+];
+
+/// Something small to stand in for an attachment, a real image where that's easy
+pub(crate) fn placeholder(path: &Path) -> &'static [u8] {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("gif") => PLACEHOLDER_GIF,
+        Some("png") => PLACEHOLDER_PNG,
+        _ => b"placeholder\n",
+    }
+}
+
+/// Keys whose values are one of a few fixed strings rather than anything someone wrote
+static FIXED_KEYS: &[&str] = &["thread_type", "type", "reaction", "animation_emoji"];
+
+#[derive(Debug)]
+pub struct Anonymizer {
+    rng: u64,
+    shift_ms: i64,
+    names: HashMap<String, String>,
+    ips: HashMap<IpAddr, IpAddr>,
+    /// Where the thread will be, relative to the export root
+    thread_folder: PathBuf,
+    /// E2EE files have media paths relative to the file
+    relative_media: bool,
+    /// Placeholder files to write, relative to the export root
+    pub attachments: Vec<PathBuf>,
+}
+
+impl Anonymizer {
+    /// `thread_folder` is where the rewritten thread goes, relative to the export root
+    pub fn new(seed: u64, shift_days: i64, thread_folder: PathBuf) -> Self {
+        Self {
+            // xorshift gets stuck on zero
+            rng: seed ^ 0x9e37_79b9_7f4a_7c15,
+            shift_ms: shift_days.saturating_mul(24 * 60 * 60 * 1000),
+            names: HashMap::new(),
+            ips: HashMap::new(),
+            thread_folder,
+            relative_media: false,
+            attachments: Vec::new(),
+        }
+    }
+
+    fn next(&mut self) -> u64 {
+        let mut x = self.rng;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.rng = x;
+        x
+    }
+
+    /// Adds the names from a message file, so they can be found in text later on. E2EE files
+    /// list participants as plain strings and have `senderName` instead.
+    pub fn learn_names(&mut self, file: &Value) {
+        let participants = file["participants"].as_array().into_iter().flatten();
+        let senders = file["messages"].as_array().into_iter().flatten();
+        let names = participants
+            .map(|p| if p.is_string() { p } else { &p["name"] })
+            .chain(senders.flat_map(|m| [&m["sender_name"], &m["senderName"]]));
+        for name in names.filter_map(|name| name.as_str()) {
+            self.pseudonym(name);
+        }
+    }
+
+    fn pseudonym(&mut self, name: &str) -> String {
+        let count = self.names.len();
+        self.names
+            .entry(name.to_string())
+            .or_insert_with(|| format!("Person {}", count + 1))
+            .clone()
+    }
+
+    fn scramble(&mut self, text: &str) -> String {
+        text.chars()
+            .map(|c| {
+                let random = self.next();
+                if c.is_ascii_digit() {
+                    (b'0' + (random % 10) as u8) as char
+                } else if c.is_uppercase() {
+                    (b'A' + (random % 26) as u8) as char
+                } else if c.is_alphabetic() {
+                    (b'a' + (random % 26) as u8) as char
+                } else {
+                    c
+                }
+            })
+            .collect()
+    }
+
+    /// Swaps any known names in the text for their pseudonyms, and scrambles the rest unless
+    /// it's a system notice
+    fn text(&mut self, text: &str) -> String {
+        let event = SystemEvent::parse(text);
+        let keep_wording = event.is_some();
+        // people who've left or been removed aren't participants any more, so they'd only be
+        // found here
+        if let Some(event) = event.as_ref() {
+            for name in std::iter::once(event.actor()).chain(event.targets()) {
+                if name != "You" {
+                    self.pseudonym(name);
+                }
+            }
+        }
+        let mut names: Vec<(String, String)> = self
+            .names
+            .iter()
+            .map(|(name, pseudonym)| (name.clone(), pseudonym.clone()))
+            .collect();
+        // longest first, so "Bob Smith" wins over "Bob"
+        names.sort_by(|a, b| b.0.len().cmp(&a.0.len()).then_with(|| a.0.cmp(&b.0)));
+
+        let mut output = String::new();
+        let mut rest = text;
+        while !rest.is_empty() {
+            let next_name = names
+                .iter()
+                .filter_map(|(name, pseudonym)| {
+                    rest.find(name.as_str()).map(|i| (i, name, pseudonym))
+                })
+                .min_by_key(|(index, _, _)| *index);
+            let (before, after) = match next_name {
+                Some((index, name, pseudonym)) => {
+                    let (before, after) = rest.split_at(index);
+                    (before, Some((pseudonym.clone(), &after[name.len()..])))
+                }
+                None => (rest, None),
+            };
+            if keep_wording {
+                output.push_str(before);
+            } else {
+                output.push_str(&self.scramble(before));
+            }
+            match after {
+                Some((pseudonym, after)) => {
+                    output.push_str(&pseudonym);
+                    rest = after;
+                }
+                None => rest = "",
+            }
+        }
+        // group names, nicknames and the like are still private
+        let value = event.as_ref().and_then(|event| event.value());
+        if let Some(index) = value.and_then(|value| output.rfind(value)) {
+            let value = value.unwrap_or_default();
+            let scrambled = self.scramble(value);
+            output.replace_range(index..index + value.len(), &scrambled);
+        }
+        output
+    }
+
+    fn ip(&mut self, ip: &str) -> String {
+        let ip: IpAddr = match ip.parse() {
+            Ok(ip) => ip,
+            Err(_) => return self.scramble(ip),
+        };
+        let count = self.ips.len() as u32;
+        // documentation ranges, so they can't be anyone's real address
+        self.ips
+            .entry(ip)
+            .or_insert_with(|| match ip {
+                IpAddr::V4(_) => IpAddr::from([192, 0, 2, (count % 254 + 1) as u8]),
+                IpAddr::V6(_) => {
+                    let last = (count % u16::MAX as u32) as u16 + 1;
+                    IpAddr::from([0x2001, 0xdb8, 0, 0, 0, 0, 0, last])
+                }
+            })
+            .to_string()
+    }
+
+    /// Points the attachment at a placeholder in the new thread folder, keeping the extension
+    /// and the folder it was in, eg `photos`
+    fn uri(&mut self, uri: &str) -> String {
+        if uri.contains("://") {
+            return self.link(uri);
+        }
+        let path = Path::new(uri);
+        let folder = path
+            .parent()
+            .and_then(|parent| parent.file_name())
+            .map(PathBuf::from)
+            .unwrap_or_default();
+        let mut name = PathBuf::from((self.attachments.len() + 1).to_string());
+        if let Some(extension) = path.extension() {
+            name.set_extension(extension);
+        }
+        let new_path = self.thread_folder.join(&folder).join(&name);
+        self.attachments.push(new_path.clone());
+        match self.relative_media {
+            true => format!("./{}", folder.join(name).display()),
+            false => new_path.display().to_string(),
+        }
+    }
+
+    /// Keeps the scheme and host so links still look like links
+    fn link(&mut self, link: &str) -> String {
+        match link
+            .find("://")
+            .and_then(|start| link[start + 3..].find('/').map(|end| start + 3 + end))
+        {
+            Some(path_start) => format!(
+                "{}{}",
+                &link[..path_start],
+                self.scramble(&link[path_start..])
+            ),
+            None => link.to_string(),
+        }
+    }
+
+    /// Moves a timestamp back by the shift. Keys ending in `_ms` are in milliseconds, as is
+    /// anything too big to be seconds, like the E2EE `timestamp`.
+    fn timestamp(&self, key: &str, value: &Value) -> Value {
+        match value.as_i64() {
+            Some(timestamp) => {
+                let shift = match key.ends_with("_ms") || timestamp > 100_000_000_000 {
+                    true => self.shift_ms,
+                    false => self.shift_ms / 1000,
+                };
+                Value::from(timestamp.saturating_sub(shift).max(0))
+            }
+            None => value.clone(),
+        }
+    }
+
+    fn value(&mut self, key: &str, value: &Value) -> Value {
+        match (key, value) {
+            (_, Value::Object(object)) => Value::Object(self.object(object)),
+            (_, Value::Array(array)) => {
+                Value::Array(array.iter().map(|item| self.value(key, item)).collect())
+            }
+            (key, Value::Number(_)) if key.contains("timestamp") => self.timestamp(key, value),
+            (
+                "sender_name" | "senderName" | "actor" | "name" | "participants",
+                Value::String(name),
+            ) => Value::String(self.pseudonym(name)),
+            ("uri", Value::String(uri)) => Value::String(self.uri(uri)),
+            ("link", Value::String(link)) => Value::String(self.link(link)),
+            ("ip", Value::String(ip)) => Value::String(self.ip(ip)),
+            // fixed values the parser relies on, eg `thread_type` says if it's a group
+            (key, Value::String(_)) if FIXED_KEYS.contains(&key) => value.clone(),
+            ("thread_path", Value::String(_)) => {
+                Value::String(super::thread_path_from_file(&self.thread_folder.join("x")))
+            }
+            (_, Value::String(text)) => Value::String(self.text(text)),
+            _ => value.clone(),
+        }
+    }
+
+    fn object(&mut self, object: &Map<String, Value>) -> Map<String, Value> {
+        // E2EE shares are only the link, in `text`
+        let is_link = object.get("type").and_then(Value::as_str) == Some("link");
+        object
+            .iter()
+            .map(|(key, value)| {
+                let value = match (key.as_str(), value) {
+                    ("text", Value::String(link)) if is_link => Value::String(self.link(link)),
+                    _ => self.value(key, value),
+                };
+                (key.clone(), value)
+            })
+            .collect()
+    }
+
+    /// Rewrites a message file, in either JSON format
+    pub fn anonymize_file(&mut self, file: &Value) -> Result<Value, MagicError> {
+        let object = file
+            .as_object()
+            .ok_or_else(|| MagicError::Generic("Message file isn't a JSON object".to_string()))?;
+        let format = object
+            .iter()
+            .find_map(|(key, value)| MessageFileFormat::detect(key, value))
+            .unwrap_or_default();
+        self.relative_media = format == MessageFileFormat::E2ee;
+        Ok(Value::Object(self.object(object)))
+    }
+}
+
+/// Anonymizes a thread into `output`, which is laid out like an export so it can be read back
+pub fn anonymize_thread(
+    export: &Export,
+    folder: &Path,
+    output: &Path,
+    seed: u64,
+    shift_days: i64,
+) -> Result<PathBuf, MagicError> {
+    let (files, html): (Vec<PathBuf>, Vec<PathBuf>) = find_message_files(export, folder)
+        .into_iter()
+        .partition(|file| file.extension().is_some_and(|ext| ext == "json"));
+    if files.is_empty() {
+        return Err(MagicError::Generic(match html.is_empty() {
+            true => format!("No JSON message files in {}", folder.display()),
+            false => format!(
+                "{} is an HTML thread, HTML threads can't be anonymized",
+                folder.display()
+            ),
+        }));
+    }
+    let category = super::threads::ThreadCategory::from_thread_folder(folder)
+        .map(|category| category.path().to_string())
+        .unwrap_or_else(|| "inbox".to_string());
+    let thread_folder = ActivityTypes::Messages
+        .path()
+        .join(category)
+        .join(format!("anonymized_{}", seed));
+    let mut anonymizer = Anonymizer::new(seed, shift_days, thread_folder.clone());
+
+    let mut parsed = Vec::new();
+    for file in files.iter() {
+        let value: Value = serde_json::from_reader(export.open(file)?)
+            .map_err(|err| MagicError::Generic(format!("{}: {}", file.display(), err)))?;
+        anonymizer.learn_names(&value);
+        parsed.push((file, value));
+    }
+
+    let target = output.join(&thread_folder);
+    std::fs::create_dir_all(&target)
+        .map_err(|err| MagicError::Generic(format!("{}: {}", target.display(), err)))?;
+    for (file, value) in parsed {
+        let anonymized = anonymizer.anonymize_file(&value)?;
+        let path = target.join(file.file_name().unwrap_or_default());
+        let contents = serde_json::to_string_pretty(&anonymized)
+            .map_err(|err| MagicError::Generic(err.to_string()))?;
+        std::fs::write(&path, contents)
+            .map_err(|err| MagicError::Generic(format!("{}: {}", path.display(), err)))?;
+        // make sure what's written is something the parser's happy with
+        MessageFileParser::try_from(&path)?;
+    }
+
+    for attachment in anonymizer.attachments.iter() {
+        let path = output.join(attachment);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|err| MagicError::Generic(format!("{}: {}", parent.display(), err)))?;
+        }
+        std::fs::write(&path, placeholder(&path))
+            .map_err(|err| MagicError::Generic(format!("{}: {}", path.display(), err)))?;
+    }
+    Ok(target)
+}
+
+pub fn anonymize(
+    export: &Export,
+    msg: ActivityMessages,
+    output: PathBuf,
+    seed: u64,
+    shift_days: Option<i64>,
+) -> Result<(), MagicError> {
+    let folder = target_folder(export, &msg);
+    // somewhere up to ten years back, unless it's been given
+    let shift_days = shift_days.unwrap_or((seed % 3650 + 1) as i64);
+    let target = anonymize_thread(export, &folder, &output, seed, shift_days)?;
+    println!("Wrote anonymized thread to {}", target.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::{anonymize_thread, Anonymizer};
+    use crate::activity::messages::kind::{MessageKind, SystemEvent};
+    use crate::activity::messages::MessageFileParser;
+    use crate::source::{DirectorySource, Export};
+    use crate::test_util::{temp_dir, write_file};
+    use crate::MagicError;
+
+    #[test]
+    fn test_anonymize_file() {
+        let file = serde_json::json!({
+            "participants": [{"name": "Bob Smith"}, {"name": "Alice Jones"}],
+            "messages": [
+                {"sender_name": "Bob Smith", "timestamp_ms": 1700000000000u64,
+                 "content": "Hi Alice Jones, call me on 0400 123!",
+                 "photos": [{"uri": "your_activity_across_facebook/messages/inbox/bobsmith_1/photos/123.jpg",
+                             "creation_timestamp": 1700000000}],
+                 "ip": "203.0.113.9", "is_geoblocked_for_viewer": false},
+                {"sender_name": "Alice Jones", "timestamp_ms": 1699999999000u64,
+                 "content": "Alice Jones added Bob Smith to the group.",
+                 "is_geoblocked_for_viewer": false},
+                {"sender_name": "Alice Jones", "timestamp_ms": 1699999998000u64,
+                 "content": "Alice Jones removed Carol Brown from the group.",
+                 "is_geoblocked_for_viewer": false}
+            ],
+            "title": "Bob Smith",
+            "is_still_participant": true,
+            "thread_path": "inbox/bobsmith_1",
+            "magic_words": [],
+            "thread_type": "RegularGroup"
+        });
+        let mut anonymizer = Anonymizer::new(
+            1,
+            1,
+            PathBuf::from("your_activity_across_facebook/messages/inbox/anonymized_1"),
+        );
+        anonymizer.learn_names(&file);
+        let anonymized = anonymizer.anonymize_file(&file).unwrap();
+
+        let text = serde_json::to_string(&anonymized).unwrap();
+        for private in [
+            "Bob",
+            "Alice",
+            "Carol",
+            "Brown",
+            "0400",
+            "203.0.113.9",
+            "bobsmith",
+        ] {
+            assert!(!text.contains(private), "{} is still in {}", private, text);
+        }
+
+        let parsed = MessageFileParser::from_reader(
+            text.as_bytes(),
+            Path::new("your_activity_across_facebook/messages/inbox/anonymized_1/message_1.json"),
+        )
+        .unwrap();
+        assert_eq!(parsed.title, "Person 1");
+        assert_eq!(parsed.thread_type.as_deref(), Some("RegularGroup"));
+        assert!(parsed.is_group());
+        assert_eq!(parsed.thread_path, "inbox/anonymized_1");
+        let first = &parsed.messages[0];
+        assert_eq!(first.sender_name, "Person 1");
+        assert_eq!(first.timestamp_ms, 1700000000000 - 24 * 60 * 60 * 1000);
+        let content = first.content.as_deref().unwrap();
+        assert!(content[3..].starts_with("Person 2, "));
+        assert_eq!(content.len(), "Hi Person 2, call me on 0400 123!".len());
+        assert_eq!(
+            first.photos.as_ref().unwrap()[0].uri,
+            "your_activity_across_facebook/messages/inbox/anonymized_1/photos/1.jpg"
+        );
+        assert_eq!(
+            parsed.messages[1].kind(),
+            MessageKind::System(SystemEvent::ParticipantsAdded {
+                actor: "Person 2".to_string(),
+                added: vec!["Person 1".to_string()],
+            })
+        );
+        assert_eq!(
+            parsed.messages[2].kind(),
+            MessageKind::System(SystemEvent::ParticipantRemoved {
+                actor: "Person 2".to_string(),
+                removed: "Person 3".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_anonymize_e2ee_file() {
+        let file = serde_json::json!({
+            "participants": ["Alice Jones", "Bob Smith"],
+            "threadName": "Bob Smith",
+            "messages": [
+                {"isUnsent": false,
+                 "media": [{"uri": "./media/1.jpg", "creation_timestamp_ms": 1700000001000u64}],
+                 "reactions": [{"actor": "Alice Jones", "reaction": "x"}], "senderName": "Bob Smith",
+                 "text": "", "timestamp": 1700000002000u64, "type": "media"},
+                {"isUnsent": false, "media": [], "reactions": [], "senderName": "Alice Jones",
+                 "text": "https://example.com/article", "timestamp": 1700000003000u64,
+                 "type": "link"}
+            ]
+        });
+        let mut anonymizer = Anonymizer::new(
+            1,
+            1,
+            PathBuf::from("your_activity_across_facebook/messages/e2ee_cutover/anonymized_1"),
+        );
+        anonymizer.learn_names(&file);
+        let anonymized = anonymizer.anonymize_file(&file).unwrap();
+
+        let text = serde_json::to_string(&anonymized).unwrap();
+        for private in ["Bob", "Alice"] {
+            assert!(!text.contains(private), "{} is still in {}", private, text);
+        }
+        let day_ms = 24 * 60 * 60 * 1000;
+        let message = &anonymized["messages"][0];
+        assert_eq!(message["timestamp"], 1700000002000u64 - day_ms);
+        assert_eq!(
+            message["media"][0]["creation_timestamp_ms"],
+            1700000001000u64 - day_ms
+        );
+        assert_eq!(message["media"][0]["uri"], "./media/1.jpg");
+        assert_eq!(
+            anonymizer.attachments,
+            vec![PathBuf::from(
+                "your_activity_across_facebook/messages/e2ee_cutover/anonymized_1/media/1.jpg"
+            )]
+        );
+
+        let parsed = MessageFileParser::from_reader(
+            text.as_bytes(),
+            Path::new(
+                "your_activity_across_facebook/messages/e2ee_cutover/anonymized_1/message_1.json",
+            ),
+        )
+        .unwrap();
+        assert_eq!(parsed.participants[1].name, "Person 2");
+        assert_eq!(parsed.messages[0].sender_name, "Person 2");
+        let link = parsed.messages[1].share.as_ref().unwrap().link.as_deref();
+        assert!(link.unwrap().starts_with("https://example.com/"));
+    }
+
+    #[test]
+    fn test_anonymize_extremes() {
+        let file = serde_json::json!({
+            "participants": [{"name": "Bob Smith"}],
+            "messages": [
+                {"sender_name": "Bob Smith", "timestamp_ms": 1700000000000u64,
+                 "is_geoblocked_for_viewer": false}
+            ],
+            "title": "Bob Smith",
+            "is_still_participant": true,
+            "thread_path": "inbox/bobsmith_1",
+            "magic_words": []
+        });
+        let mut anonymizer = Anonymizer::new(1, i64::MAX, PathBuf::from("inbox/anonymized_1"));
+        let anonymized = anonymizer.anonymize_file(&file).unwrap();
+        assert_eq!(anonymized["messages"][0]["timestamp_ms"], 0);
+
+        for i in 0..70_000u32 {
+            let ip = std::net::Ipv6Addr::from(i as u128 + 1).to_string();
+            anonymizer.ip(&ip);
+        }
+    }
+
+    #[test]
+    fn test_anonymize_html_thread() {
+        let temp = temp_dir();
+        let root = temp.path().to_path_buf();
+        let folder = Path::new("your_activity_across_facebook/messages/inbox/bobsmith_1");
+        write_file(&root, folder.join("message_1.html"), "<html></html>");
+        let export = Export::from_source(DirectorySource::new(&root));
+
+        match anonymize_thread(&export, folder, &root.join("output"), 1, 1) {
+            Err(MagicError::Generic(err)) => assert!(err.contains("HTML"), "{}", err),
+            other => panic!("expected an error, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,301 @@
+//!
+//! Audio and video calls, pulled out of the threads they happened in
+//!
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Duration, Utc};
+use rayon::prelude::*;
+use serde::Serialize;
+
+use super::id::MessageId;
+use super::kind::MessageKind;
+use super::stream::MessageStream;
+use super::threads::ThreadInfo;
+use super::{selected_threads, Message};
+use crate::source::Export;
+use crate::{ActivityMessages, MagicError};
+
+#[derive(Serialize, Debug, Clone)]
+pub struct CallRecord {
+    pub id: MessageId,
+    pub thread: PathBuf,
+    /// The thread's title, which for one-to-one chats is the other person
+    pub contact: String,
+    pub caller: String,
+    pub start: DateTime<Utc>,
+    pub duration_secs: u64,
+    pub missed: bool,
+    pub video: bool,
+}
+
+impl CallRecord {
+    /// `None` if the message isn't a call
+    pub fn from_message(thread: &ThreadInfo, message: &Message) -> Option<Self> {
+        let (duration_secs, missed) = match message.kind() {
+            MessageKind::Call { duration } => (duration, false),
+            MessageKind::MissedCall => (0, true),
+            _ => return None,
+        };
+        Some(Self {
+            id: message.id,
+            thread: thread.folder.clone(),
+            contact: thread.title.clone(),
+            caller: message.sender_name.clone(),
+            start: DateTime::<Utc>::from_timestamp_millis(message.timestamp_ms as i64)?,
+            duration_secs,
+            missed,
+            // the export only says which it was in the text, eg "The video call ended."
+            video: message
+                .content
+                .as_deref()
+                .is_some_and(|content| content.to_lowercase().contains("video")),
+        })
+    }
+
+    pub fn end(&self) -> DateTime<Utc> {
+        self.start + Duration::seconds(self.duration_secs as i64)
+    }
+}
+
+/// Every call in the given threads, oldest first
+pub fn find_calls(export: &Export, threads: &[ThreadInfo]) -> Result<Vec<CallRecord>, MagicError> {
+    let per_thread = threads
+        .par_iter()
+        .map(|thread| {
+            let mut calls = Vec::new();
+            for message in MessageStream::new(export, &thread.folder) {
+                calls.extend(CallRecord::from_message(thread, &message?));
+            }
+            Ok(calls)
+        })
+        .collect::<Result<Vec<Vec<CallRecord>>, MagicError>>()?;
+    let mut calls: Vec<CallRecord> = per_thread.into_iter().flatten().collect();
+    calls.sort_by_key(|call| (call.start, call.id));
+    Ok(calls)
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct CallTotals {
+    pub calls: usize,
+    pub missed: usize,
+    pub duration_secs: u64,
+}
+
+impl CallTotals {
+    fn add(&mut self, call: &CallRecord) {
+        self.calls += 1;
+        self.missed += call.missed as usize;
+        self.duration_secs += call.duration_secs;
+    }
+}
+
+/// Totals keyed by whatever `key` picks out of each call
+pub fn call_totals<F>(calls: &[CallRecord], key: F) -> BTreeMap<String, CallTotals>
+where
+    F: Fn(&CallRecord) -> String,
+{
+    let mut totals: BTreeMap<String, CallTotals> = BTreeMap::new();
+    for call in calls {
+        totals.entry(key(call)).or_default().add(call);
+    }
+    totals
+}
+
+fn format_duration(secs: u64) -> String {
+    format!("{}:{:02}:{:02}", secs / 3600, (secs / 60) % 60, secs % 60)
+}
+
+fn print_totals(heading: &str, totals: &BTreeMap<String, CallTotals>) {
+    println!("{}", heading);
+    for (key, total) in totals.iter() {
+        println!(
+            "  {}: {} calls, {} missed, {}",
+            key,
+            total.calls,
+            total.missed,
+            format_duration(total.duration_secs)
+        );
+    }
+}
+
+pub fn write_calls_csv(calls: &[CallRecord], writer: impl Write) -> Result<(), MagicError> {
+    let mut writer = csv::Writer::from_writer(writer);
+    for call in calls {
+        writer
+            .serialize(call)
+            .map_err(|err| MagicError::Generic(err.to_string()))?;
+    }
+    writer
+        .flush()
+        .map_err(|err| MagicError::Generic(err.to_string()))
+}
+
+/// Escapes text for an iCalendar property value
+fn ics_escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+/// Lines longer than 75 bytes get folded onto continuation lines starting with a space
+fn ics_line(line: &str) -> String {
+    let mut folded = String::new();
+    let mut length = 0;
+    for c in line.chars() {
+        if length + c.len_utf8() > 75 {
+            folded.push_str("\r\n ");
+            length = 1;
+        }
+        folded.push(c);
+        length += c.len_utf8();
+    }
+    folded.push_str("\r\n");
+    folded
+}
+
+pub fn write_calls_ics(calls: &[CallRecord], mut writer: impl Write) -> Result<(), MagicError> {
+    let ics_time = |time: DateTime<Utc>| time.format("%Y%m%dT%H%M%SZ").to_string();
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        "PRODID:-//facebook_data_parser//calls//EN".to_string(),
+    ];
+    for call in calls {
+        let kind = if call.video { "video call" } else { "call" };
+        let summary = if call.missed {
+            format!("Missed {} from {}", kind, call.caller)
+        } else if call.video {
+            format!("Video call with {}", call.contact)
+        } else {
+            format!("Call with {}", call.contact)
+        };
+        lines.extend([
+            "BEGIN:VEVENT".to_string(),
+            format!("UID:{}@facebook_data_parser", call.id),
+            format!("DTSTAMP:{}", ics_time(call.start)),
+            format!("DTSTART:{}", ics_time(call.start)),
+            format!("DTEND:{}", ics_time(call.end())),
+            format!("SUMMARY:{}", ics_escape(&summary)),
+            format!(
+                "DESCRIPTION:{}",
+                ics_escape(&format!(
+                    "Started by {} in {}",
+                    call.caller,
+                    call.thread.display()
+                ))
+            ),
+            "END:VEVENT".to_string(),
+        ]);
+    }
+    lines.push("END:VCALENDAR".to_string());
+
+    for line in lines {
+        writer
+            .write_all(ics_line(&line).as_bytes())
+            .map_err(|err| MagicError::Generic(err.to_string()))?;
+    }
+    Ok(())
+}
+
+fn create_file(path: &Path) -> Result<File, MagicError> {
+    File::create(path)
+        .map_err(|err| MagicError::Generic(format!("Failed to create {}: {}", path.display(), err)))
+}
+
+/// List calls in the thread given on the command line, or every thread if there isn't one
+pub fn list_calls(
+    export: &Export,
+    msg: ActivityMessages,
+    csv: Option<PathBuf>,
+    ics: Option<PathBuf>,
+) -> Result<(), MagicError> {
+    let threads = selected_threads(export, &msg)?;
+    let calls = find_calls(export, &threads)?;
+
+    for call in calls.iter() {
+        println!(
+            "{} {} {} started by {} in {}, {}{}",
+            call.start.to_rfc3339(),
+            if call.video { "video" } else { "audio" },
+            call.contact,
+            call.caller,
+            call.thread.display(),
+            format_duration(call.duration_secs),
+            if call.missed { " (missed)" } else { "" }
+        );
+    }
+    print_totals(
+        "Per contact:",
+        &call_totals(&calls, |call| call.contact.clone()),
+    );
+    print_totals(
+        "Per month:",
+        &call_totals(&calls, |call| call.start.format("%Y-%m").to_string()),
+    );
+
+    if let Some(path) = csv {
+        write_calls_csv(&calls, create_file(&path)?)?;
+        println!("Wrote {} calls to {}", calls.len(), path.display());
+    }
+    if let Some(path) = ics {
+        write_calls_ics(&calls, create_file(&path)?)?;
+        println!("Wrote {} calls to {}", calls.len(), path.display());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{call_totals, write_calls_ics, CallRecord, CallTotals};
+    use crate::activity::messages::threads::ThreadInfo;
+    use crate::activity::messages::Message;
+
+    #[test]
+    fn test_calls() {
+        let thread = ThreadInfo {
+            folder: PathBuf::from("your_activity_across_facebook/messages/inbox/bob_1"),
+            category: None,
+            title: "Bob".to_string(),
+            participants: vec!["Alice".to_string(), "Bob".to_string()],
+            is_group: false,
+        };
+        let messages: Vec<Message> = serde_json::from_value(serde_json::json!([
+            {"sender_name": "Bob", "timestamp_ms": 1700000000000u64, "call_duration": 90,
+             "content": "The video call ended.", "is_geoblocked_for_viewer": false},
+            {"sender_name": "Bob", "timestamp_ms": 1700000100000u64, "call_duration": 0,
+             "missed": true, "content": "Alice missed your call.", "is_geoblocked_for_viewer": false},
+            {"sender_name": "Bob", "timestamp_ms": 1700000200000u64, "content": "hi",
+             "is_geoblocked_for_viewer": false},
+        ]))
+        .unwrap();
+        let calls: Vec<CallRecord> = messages
+            .iter()
+            .filter_map(|message| CallRecord::from_message(&thread, message))
+            .collect();
+        assert_eq!(calls.len(), 2);
+        assert!(calls[0].video);
+        assert!(calls[1].missed);
+
+        let totals = call_totals(&calls, |call| call.start.format("%Y-%m").to_string());
+        assert_eq!(
+            totals.get("2023-11"),
+            Some(&CallTotals {
+                calls: 2,
+                missed: 1,
+                duration_secs: 90
+            })
+        );
+
+        let mut ics = Vec::new();
+        write_calls_ics(&calls, &mut ics).unwrap();
+        let ics = String::from_utf8(ics).unwrap();
+        assert!(ics.contains("DTSTART:20231114T221320Z\r\nDTEND:20231114T221450Z\r\n"));
+        assert!(ics.contains("SUMMARY:Missed call from Bob\r\n"));
+    }
+}
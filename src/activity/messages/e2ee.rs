@@ -0,0 +1,306 @@
+//!
+//! The end-to-end encrypted chat format, which Messenger exports with its own schema
+//!
+//! The files look like this, with media paths relative to the JSON file:
+//!
+//! ```json
+//! {
+//!   "participants": ["Alice", "Bob"],
+//!   "threadName": "Alice",
+//!   "messages": [
+//!     {"isUnsent": false, "media": [{"uri": "./media/1.jpg"}], "reactions": [],
+//!      "senderName": "Bob", "text": "", "timestamp": 1700000000000, "type": "media"}
+//!   ]
+//! }
+//! ```
+//!
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::Value;
+
+use super::id::MessageId;
+use super::{
+    thread_path_from_file, Message, MessageFile, MessageFileParser, MessageMedia,
+    MessageParticipant, MessagePhoto, MessageReaction, MessageShare, MessageVideo,
+};
+
+#[derive(Deserialize, JsonSchema, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct E2eeMedia {
+    pub uri: String,
+    pub creation_timestamp_ms: Option<u64>,
+}
+
+#[derive(Deserialize, JsonSchema, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct E2eeReaction {
+    pub reaction: String,
+    pub actor: String,
+}
+
+#[derive(Deserialize, JsonSchema, Debug)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub struct E2eeMessage {
+    pub sender_name: String,
+    pub timestamp: u64,
+    pub text: Option<String>,
+    pub is_unsent: Option<bool>,
+    #[serde(default)]
+    pub media: Vec<E2eeMedia>,
+    #[serde(default)]
+    pub reactions: Vec<E2eeReaction>,
+    #[serde(rename = "type")]
+    pub message_type: Option<String>,
+}
+
+#[derive(Deserialize, JsonSchema, Debug)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub struct E2eeMessageFileParser {
+    pub participants: Vec<String>,
+    pub thread_name: String,
+    pub messages: Vec<E2eeMessage>,
+}
+
+/// Which schema a message file's using
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MessageFileFormat {
+    #[default]
+    Legacy,
+    E2ee,
+}
+
+impl MessageFileFormat {
+    /// Looks at a top-level key and its value to see if they give the format away. E2EE files
+    /// list participants as plain strings and have a `threadName`.
+    pub fn detect(key: &str, value: &Value) -> Option<Self> {
+        match key {
+            "threadName" => Some(MessageFileFormat::E2ee),
+            "participants" => match value.as_array().and_then(|p| p.first()) {
+                Some(Value::String(_)) => Some(MessageFileFormat::E2ee),
+                Some(_) => Some(MessageFileFormat::Legacy),
+                None => None,
+            },
+            "thread_path" | "is_still_participant" => Some(MessageFileFormat::Legacy),
+            _ => None,
+        }
+    }
+
+    /// Looks at a message to see which format it's in, for when `messages` comes before
+    /// anything in the header that'd say
+    pub fn detect_message(message: &Value) -> Option<Self> {
+        let message = message.as_object()?;
+        if message.contains_key("senderName") || message.contains_key("timestamp") {
+            Some(MessageFileFormat::E2ee)
+        } else if message.contains_key("sender_name") || message.contains_key("timestamp_ms") {
+            Some(MessageFileFormat::Legacy)
+        } else {
+            None
+        }
+    }
+}
+
+/// Media paths are relative to the JSON file, make them relative to the export like the others
+fn media_uri(uri: &str, thread_folder: &Path) -> String {
+    let uri = uri.trim_start_matches("./");
+    if uri.contains("://") {
+        return uri.to_string();
+    }
+    thread_folder.join(uri).display().to_string()
+}
+
+fn extension(uri: &str) -> String {
+    Path::new(uri)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or_default()
+        .to_lowercase()
+}
+
+impl E2eeMessage {
+    /// Maps into the same model as the legacy export. `thread_folder` is the folder the file's
+    /// in, so media ends up with a path relative to the export.
+    pub fn into_message(self, thread_folder: &Path) -> Message {
+        let timestamp = DateTime::<Utc>::from_timestamp_millis(self.timestamp as i64);
+        let text = self.text.filter(|text| !text.is_empty());
+        let mut message = Message {
+            id: MessageId::default(),
+            sender_name: self.sender_name,
+            is_unsent: self.is_unsent,
+            timestamp_ms: self.timestamp,
+            content: None,
+            share: None,
+            videos: None,
+            reactions: None,
+            photos: None,
+            gifs: None,
+            is_geoblocked_for_viewer: false,
+            call_duration: None,
+            sticker: None,
+            files: None,
+            audio_files: None,
+            ip: None,
+            missed: None,
+        };
+        if self.message_type.as_deref() == Some("link") {
+            message.share = Some(MessageShare {
+                link: text.clone(),
+                share_text: None,
+                is_geoblocked_for_viewer: None,
+            });
+        }
+        message.content = text;
+
+        if !self.reactions.is_empty() {
+            message.reactions = Some(
+                self.reactions
+                    .into_iter()
+                    .map(|r| MessageReaction {
+                        reaction: r.reaction,
+                        actor: r.actor,
+                    })
+                    .collect(),
+            );
+        }
+
+        for media in self.media {
+            let uri = media_uri(&media.uri, thread_folder);
+            let created = media
+                .creation_timestamp_ms
+                .and_then(|ms| DateTime::<Utc>::from_timestamp_millis(ms as i64));
+            match extension(&uri).as_str() {
+                "gif" => message
+                    .gifs
+                    .get_or_insert_with(Vec::new)
+                    .push(MessagePhoto {
+                        id: MessageId::default(),
+                        uri,
+                        creation_timestamp: created,
+                    }),
+                "jpg" | "jpeg" | "png" | "webp" | "heic" => message
+                    .photos
+                    .get_or_insert_with(Vec::new)
+                    .push(MessagePhoto {
+                        id: MessageId::default(),
+                        uri,
+                        creation_timestamp: created,
+                    }),
+                // the creation time's often missing, so use when it was sent
+                "mp4" | "mov" | "webm" | "3gp" => {
+                    match created.or(timestamp) {
+                        Some(creation_timestamp) => message
+                            .videos
+                            .get_or_insert_with(Vec::new)
+                            .push(MessageVideo {
+                                id: MessageId::default(),
+                                uri,
+                                creation_timestamp,
+                            }),
+                        None => message
+                            .files
+                            .get_or_insert_with(Vec::new)
+                            .push(MessageFile {
+                                id: MessageId::default(),
+                                uri,
+                                creation_timestamp: None,
+                                title: None,
+                            }),
+                    }
+                }
+                "mp3" | "m4a" | "aac" | "ogg" | "opus" | "wav" => message
+                    .audio_files
+                    .get_or_insert_with(Vec::new)
+                    .push(MessageMedia {
+                        id: MessageId::default(),
+                        uri: Some(uri),
+                        creation_timestamp: None,
+                        share_text: None,
+                        is_geoblocked_for_viewer: None,
+                    }),
+                _ => message
+                    .files
+                    .get_or_insert_with(Vec::new)
+                    .push(MessageFile {
+                        id: MessageId::default(),
+                        uri,
+                        creation_timestamp: None,
+                        title: None,
+                    }),
+            }
+        }
+        message
+    }
+}
+
+impl E2eeMessageFileParser {
+    /// `path` is the message file, which is where the thread path comes from
+    pub fn into_message_file(self, path: &Path) -> MessageFileParser {
+        let thread_folder = path.parent().unwrap_or(Path::new(""));
+        // there's nothing in E2EE files that says it's a group, so go by who's in it
+        let thread_type = match self.participants.len() > 2 {
+            true => "RegularGroup",
+            false => "Regular",
+        };
+        MessageFileParser {
+            participants: self
+                .participants
+                .into_iter()
+                .map(|name| MessageParticipant { name })
+                .collect(),
+            messages: self
+                .messages
+                .into_iter()
+                .map(|message| message.into_message(thread_folder))
+                .collect(),
+            title: self.thread_name,
+            is_still_participant: true,
+            thread_path: thread_path_from_file(path),
+            magic_words: Vec::new(),
+            image: None,
+            joinable_mode: None,
+            thread_type: Some(thread_type.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::activity::messages::MessageFileParser;
+
+    static TEST_FILE: &str = r#"{
+        "participants": ["Alice Jones", "Bob Smith"],
+        "threadName": "Bob Smith",
+        "messages": [
+            {"isUnsent": false, "media": [{"uri": "./media/1.jpg"}, {"uri": "./media/2.mp4"}],
+             "reactions": [{"actor": "Alice Jones", "reaction": "❤"}], "senderName": "Bob Smith",
+             "text": "", "timestamp": 1700000002000, "type": "media"},
+            {"isUnsent": false, "media": [], "reactions": [], "senderName": "Alice Jones",
+             "text": "hello", "timestamp": 1700000001000, "type": "text"}
+        ]
+    }"#;
+
+    #[test]
+    fn test_e2ee_message_file() {
+        let path =
+            Path::new("your_activity_across_facebook/messages/e2ee_cutover/bob_1/message_1.json");
+        let parsed = MessageFileParser::from_reader(TEST_FILE.as_bytes(), path).unwrap();
+        assert_eq!(parsed.title, "Bob Smith");
+        assert_eq!(parsed.thread_path, "e2ee_cutover/bob_1");
+        assert_eq!(parsed.participants[1].name, "Bob Smith");
+        assert_eq!(parsed.messages.len(), 2);
+
+        let media = &parsed.messages[0];
+        assert_eq!(media.content, None);
+        assert_eq!(
+            media.photos.as_ref().unwrap()[0].uri,
+            "your_activity_across_facebook/messages/e2ee_cutover/bob_1/media/1.jpg"
+        );
+        assert_eq!(media.videos.as_ref().unwrap().len(), 1);
+        assert_eq!(media.reactions.as_ref().unwrap()[0].actor, "Alice Jones");
+        assert_eq!(parsed.messages[1].content.as_deref(), Some("hello"));
+    }
+}
@@ -0,0 +1,373 @@
+//!
+//! Parser for the HTML version of the export, which has `message_1.html` files instead of JSON
+//!
+//! The HTML is generated with minified class names, these are the ones seen in the wild so far,
+//! the old set (`_2lej`, ...) and the current one (`_a6-g`, ...).
+//!
+//! The dates in the page are in the local time of whoever downloaded it and don't say which
+//! timezone that was, so they're read as UTC and a warning's printed the first time.
+//!
+use std::io::Read;
+use std::path::Path;
+use std::sync::Once;
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use scraper::{ElementRef, Html, Node, Selector};
+
+use super::id::MessageId;
+use super::{
+    thread_path_from_file, Message, MessageFile, MessageFileParser, MessageMedia,
+    MessageParticipant, MessagePhoto, MessageReaction, MessageShare, MessageSticker, MessageVideo,
+};
+use crate::MagicError;
+
+static MESSAGE_SELECTOR: &str = "div._a6-g, div._2lej";
+static SENDER_SELECTOR: &str = "._a6-h, ._2lek";
+static CONTENT_SELECTOR: &str = "._a6-p, ._2let";
+static TIMESTAMP_SELECTOR: &str = "._a6-o, ._2lem";
+static REACTION_SELECTOR: &str = "ul li";
+/// Elements that start a new line of text
+static BLOCK_ELEMENTS: [&str; 10] = ["div", "p", "br", "li", "h1", "h2", "h3", "h4", "h5", "h6"];
+
+static TIMEZONE_WARNING: Once = Once::new();
+
+/// The date formats used in the page, they're in the local time of whoever downloaded it
+static TIMESTAMP_FORMATS: [&str; 3] = [
+    "%b %d, %Y %I:%M:%S %p",
+    "%b %d, %Y, %I:%M %p",
+    "%b %d, %Y %I:%M %p",
+];
+
+pub fn is_html_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("html"))
+}
+
+fn selector(selector: &str) -> Selector {
+    Selector::parse(selector).expect("Invalid selector")
+}
+
+/// Parses the timestamp line. It has no timezone, so it's read as if it was UTC, which it
+/// probably isn't - times will be out by the downloader's UTC offset.
+fn parse_timestamp(value: &str) -> Option<DateTime<Utc>> {
+    TIMEZONE_WARNING.call_once(|| {
+        eprintln!(
+            "HTML exports don't say what timezone their dates are in, they're being read as UTC"
+        )
+    });
+    let value = value.trim().to_uppercase();
+    TIMESTAMP_FORMATS
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(&value, format).ok())
+        .map(|dt| dt.and_utc())
+}
+
+/// Adds the text under an element, a line per block, skipping the reactions list that's nested
+/// in the content
+fn push_text(element: ElementRef, text: &mut String) {
+    for child in element.children() {
+        match child.value() {
+            Node::Text(value) => text.push_str(value),
+            Node::Element(value) if value.name() == "ul" => continue,
+            Node::Element(value) => {
+                let block = BLOCK_ELEMENTS.contains(&value.name());
+                if block {
+                    text.push('\n');
+                }
+                if let Some(child) = ElementRef::wrap(child) {
+                    push_text(child, text);
+                }
+                if block {
+                    text.push('\n');
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The text of an element, with the page's indenting taken out and a line per block
+fn element_text(element: ElementRef) -> String {
+    let mut text = String::new();
+    push_text(element, &mut text);
+    text.lines()
+        .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Splits "👍Bob Smith" into the reaction and who did it
+fn parse_reaction(value: &str) -> Option<MessageReaction> {
+    let value = value.trim();
+    let split = value.find(|c: char| c.is_alphanumeric())?;
+    if split == 0 {
+        return None;
+    }
+    Some(MessageReaction {
+        reaction: value[..split].trim().to_string(),
+        actor: value[split..].trim().to_string(),
+    })
+}
+
+fn parse_message(element: ElementRef) -> Result<Message, MagicError> {
+    let sender_name = element
+        .select(&selector(SENDER_SELECTOR))
+        .next()
+        .map(element_text)
+        .ok_or_else(|| MagicError::Generic("Message has no sender".to_string()))?;
+    let timestamp_text = element
+        .select(&selector(TIMESTAMP_SELECTOR))
+        .next()
+        .map(element_text)
+        .ok_or_else(|| MagicError::Generic(format!("Message from {} has no date", sender_name)))?;
+    let timestamp = parse_timestamp(&timestamp_text)
+        .ok_or_else(|| MagicError::Generic(format!("Failed to parse date {:?}", timestamp_text)))?;
+
+    let mut message = Message {
+        id: MessageId::default(),
+        sender_name,
+        is_unsent: None,
+        timestamp_ms: timestamp.timestamp_millis() as u64,
+        content: None,
+        share: None,
+        videos: None,
+        reactions: None,
+        photos: None,
+        gifs: None,
+        is_geoblocked_for_viewer: false,
+        call_duration: None,
+        sticker: None,
+        files: None,
+        audio_files: None,
+        ip: None,
+        missed: None,
+    };
+
+    let content = match element.select(&selector(CONTENT_SELECTOR)).next() {
+        Some(content) => content,
+        None => return Ok(message),
+    };
+
+    let text = element_text(content);
+    if !text.is_empty() {
+        message.content = Some(text);
+    }
+
+    let reactions: Vec<MessageReaction> = content
+        .select(&selector(REACTION_SELECTOR))
+        .filter_map(|li| parse_reaction(&element_text(li)))
+        .collect();
+    if !reactions.is_empty() {
+        message.reactions = Some(reactions);
+    }
+
+    let mut photos = Vec::new();
+    let mut gifs = Vec::new();
+    for img in content.select(&selector("img")) {
+        let uri = match img.value().attr("src") {
+            Some(uri) => uri.to_string(),
+            None => continue,
+        };
+        if uri.contains("/gifs/") {
+            gifs.push(MessagePhoto {
+                id: MessageId::default(),
+                uri,
+                creation_timestamp: None,
+            });
+        } else if uri.contains("/stickers_used/") {
+            message.sticker = Some(MessageSticker {
+                id: MessageId::default(),
+                uri,
+                ai_stickers: Vec::new(),
+            });
+        } else {
+            photos.push(MessagePhoto {
+                id: MessageId::default(),
+                uri,
+                creation_timestamp: None,
+            });
+        }
+    }
+    if !photos.is_empty() {
+        message.photos = Some(photos);
+    }
+    if !gifs.is_empty() {
+        message.gifs = Some(gifs);
+    }
+
+    // the HTML doesn't say when videos were created, so use when they were sent
+    let videos: Vec<MessageVideo> = content
+        .select(&selector("video"))
+        .filter_map(|video| video.value().attr("src"))
+        .map(|uri| MessageVideo {
+            id: MessageId::default(),
+            uri: uri.to_string(),
+            creation_timestamp: timestamp,
+        })
+        .collect();
+    if !videos.is_empty() {
+        message.videos = Some(videos);
+    }
+
+    let audio_files: Vec<MessageMedia> = content
+        .select(&selector("audio"))
+        .filter_map(|audio| audio.value().attr("src"))
+        .map(|uri| MessageMedia {
+            id: MessageId::default(),
+            uri: Some(uri.to_string()),
+            creation_timestamp: None,
+            share_text: None,
+            is_geoblocked_for_viewer: None,
+        })
+        .collect();
+    if !audio_files.is_empty() {
+        message.audio_files = Some(audio_files);
+    }
+
+    let mut files = Vec::new();
+    for link in content.select(&selector("a")) {
+        let href = match link.value().attr("href") {
+            Some(href) => href,
+            None => continue,
+        };
+        if href.starts_with("http://") || href.starts_with("https://") {
+            if message.share.is_none() {
+                message.share = Some(MessageShare {
+                    link: Some(href.to_string()),
+                    share_text: None,
+                    is_geoblocked_for_viewer: None,
+                });
+            }
+        } else if href.contains("/files/") {
+            files.push(MessageFile {
+                id: MessageId::default(),
+                uri: href.to_string(),
+                creation_timestamp: None,
+                title: None,
+            });
+        }
+    }
+    if !files.is_empty() {
+        message.files = Some(files);
+    }
+
+    Ok(message)
+}
+
+/// Parse a `message_<n>.html` file into the same model as the JSON files. `path` is used to
+/// work out the thread path, which isn't in the page.
+pub fn parse_html_message_file<R: Read>(
+    mut reader: R,
+    path: &Path,
+) -> Result<MessageFileParser, MagicError> {
+    let mut contents = String::new();
+    reader
+        .read_to_string(&mut contents)
+        .map_err(|err| MagicError::Generic(format!("{}: {}", path.display(), err)))?;
+    let document = Html::parse_document(&contents);
+
+    let title = document
+        .select(&selector("title"))
+        .next()
+        .map(element_text)
+        .unwrap_or_default();
+
+    let participants: Vec<MessageParticipant> = document
+        .root_element()
+        .descendants()
+        .filter_map(|node| node.value().as_text())
+        .find_map(|text| {
+            text.trim()
+                .strip_prefix("Participants:")
+                .map(str::to_string)
+        })
+        .map(|names| {
+            names
+                .split(", ")
+                .flat_map(|name| name.split(" and "))
+                .map(|name| MessageParticipant {
+                    name: name.trim().to_string(),
+                })
+                .filter(|participant| !participant.name.is_empty())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let messages = document
+        .select(&selector(MESSAGE_SELECTOR))
+        .map(parse_message)
+        .collect::<Result<Vec<Message>, MagicError>>()
+        .map_err(|err| MagicError::Generic(format!("{}: {:?}", path.display(), err)))?;
+
+    // the page doesn't say if it's a group either, so go by who's in it
+    let thread_type = match participants.len() > 2 {
+        true => "RegularGroup",
+        false => "Regular",
+    };
+    Ok(MessageFileParser {
+        participants,
+        messages,
+        title,
+        is_still_participant: true,
+        thread_path: thread_path_from_file(path),
+        magic_words: Vec::new(),
+        image: None,
+        joinable_mode: None,
+        thread_type: Some(thread_type.to_string()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use scraper::Html;
+
+    use super::{element_text, parse_html_message_file};
+
+    static TEST_FILE: &str = r##"<html><head><title>Bob Smith</title></head><body>
+    <div class="_a706" role="main">
+        <div class="_2ph_ _a6-h">Participants: Bob Smith and Alice Jones</div>
+        <div class="pam _3-95 _a6-g uiBoxWhite noborder">
+            <div class="_2ph_ _a6-h _a6-i">Bob Smith</div>
+            <div class="_2ph_ _a6-p"><div><div></div><div>Check this out</div>
+                <div><a href="https://example.com/article">https://example.com/article</a></div>
+                <div><img src="your_activity_across_facebook/messages/inbox/bobsmith_123/photos/1.jpg" /></div>
+                <ul class="_a6-q"><li>❤Alice Jones</li></ul>
+            </div></div>
+            <div class="_3-94 _a6-o"><a href="https://www.facebook.com/"><div class="_a72d">Jan 05, 2023 3:04:05 pm</div></a></div>
+        </div>
+    </div></body></html>"##;
+
+    #[test]
+    fn test_parse_html_message_file() {
+        let path =
+            Path::new("your_activity_across_facebook/messages/inbox/bobsmith_123/message_1.html");
+        let parsed = parse_html_message_file(TEST_FILE.as_bytes(), path).unwrap();
+        assert_eq!(parsed.title, "Bob Smith");
+        assert_eq!(parsed.thread_path, "inbox/bobsmith_123");
+        assert_eq!(parsed.participants.len(), 2);
+        assert_eq!(parsed.messages.len(), 1);
+
+        let message = &parsed.messages[0];
+        assert_eq!(message.sender_name, "Bob Smith");
+        assert_eq!(message.timestamp_ms, 1672931045000);
+        assert_eq!(
+            message.content.as_deref(),
+            Some("Check this out\nhttps://example.com/article")
+        );
+        assert_eq!(
+            message.share.as_ref().unwrap().link.as_deref(),
+            Some("https://example.com/article")
+        );
+        assert_eq!(message.photos.as_ref().unwrap().len(), 1);
+        let reaction = &message.reactions.as_ref().unwrap()[0];
+        assert_eq!(reaction.reaction, "❤");
+        assert_eq!(reaction.actor, "Alice Jones");
+
+        let fragment = Html::parse_fragment("<div>a</div><div>b <b>bold</b></div>");
+        assert_eq!(element_text(fragment.root_element()), "a\nb bold");
+    }
+}
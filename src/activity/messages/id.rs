@@ -0,0 +1,141 @@
+//!
+//! Stable identifiers for messages and their attachments
+//!
+//! IDs are derived from the content, so the same message gets the same ID every run and in
+//! every export that has it. Attachments only count by file name, the folders they're in move
+//! around between export layouts.
+//!
+use std::fmt::Display;
+use std::path::Path;
+use std::str::FromStr;
+
+use serde::{Serialize, Serializer};
+use sha2::{Digest, Sha256};
+
+use super::Message;
+use crate::MagicError;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub struct MessageId([u8; 16]);
+
+impl MessageId {
+    /// Hashes the parts with their lengths, so ("ab", "c") and ("a", "bc") don't collide
+    fn from_parts(parts: &[&[u8]]) -> Self {
+        let mut hasher = Sha256::new();
+        for part in parts {
+            hasher.update((part.len() as u64).to_le_bytes());
+            hasher.update(part);
+        }
+        let digest = hasher.finalize();
+        let mut id = [0u8; 16];
+        id.copy_from_slice(&digest[..16]);
+        Self(id)
+    }
+
+    /// The ID for a message, from the thread it's in, who sent it, when, what it said and the
+    /// names of the files attached
+    pub fn for_message(thread: &str, message: &Message) -> Self {
+        let timestamp = message.timestamp_ms.to_le_bytes();
+        let mut parts: Vec<&[u8]> = vec![
+            thread.as_bytes(),
+            message.sender_name.as_bytes(),
+            &timestamp,
+            message.content.as_deref().unwrap_or_default().as_bytes(),
+        ];
+        let uris = message.attachment_uris();
+        parts.extend(uris.iter().map(|uri| file_name(uri).as_bytes()));
+        Self::from_parts(&parts)
+    }
+
+    /// The ID for something attached to a message
+    pub fn for_attachment(message: &MessageId, uri: &str) -> Self {
+        Self::from_parts(&[&message.0, file_name(uri).as_bytes()])
+    }
+}
+
+/// The last part of an attachment's URI, which is the same whichever layout the export has
+fn file_name(uri: &str) -> &str {
+    Path::new(uri)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(uri)
+}
+
+impl Display for MessageId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for byte in self.0.iter() {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for MessageId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "MessageId({})", self)
+    }
+}
+
+impl FromStr for MessageId {
+    type Err = MagicError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() != 32 || !s.is_ascii() {
+            return Err(MagicError::Generic(format!("Invalid message ID {:?}", s)));
+        }
+        let mut id = [0u8; 16];
+        for (index, byte) in id.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&s[index * 2..index * 2 + 2], 16).map_err(|err| {
+                MagicError::Generic(format!("Invalid message ID {:?}: {}", s, err))
+            })?;
+        }
+        Ok(Self(id))
+    }
+}
+
+impl Serialize for MessageId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MessageId;
+    use crate::test_util::message;
+
+    #[test]
+    fn test_message_ids() {
+        let mut message = message(serde_json::json!({
+            "sender_name": "Bob",
+            "timestamp_ms": 1700000000000u64,
+            "content": "look",
+            "photos": [{"uri": "messages/inbox/bob_1/photos/1.jpg"}],
+        }));
+        message.assign_ids("inbox/bob_1");
+
+        let id = message.id;
+        assert_ne!(id, MessageId::default());
+        assert_eq!(id, MessageId::for_message("inbox/bob_1", &message));
+        assert_ne!(id, MessageId::for_message("inbox/alice_2", &message));
+        assert_eq!(id.to_string().parse::<MessageId>().unwrap(), id);
+
+        let photo = &message.photos.as_ref().unwrap()[0];
+        assert_eq!(photo.id, MessageId::for_attachment(&id, &photo.uri));
+        assert_eq!(
+            photo.id,
+            MessageId::for_attachment(
+                &id,
+                "your_activity_across_facebook/messages/inbox/bob_1/photos/1.jpg"
+            )
+        );
+
+        // the same photo from a newer layout is the same message
+        let mut moved = message;
+        moved.photos.as_mut().unwrap()[0].uri =
+            "your_activity_across_facebook/messages/inbox/bob_1/photos/1.jpg".to_string();
+        assert_eq!(MessageId::for_message("inbox/bob_1", &moved), id);
+        moved.photos.as_mut().unwrap()[0].uri = "messages/inbox/bob_1/photos/2.jpg".to_string();
+        assert_ne!(MessageId::for_message("inbox/bob_1", &moved), id);
+    }
+}
@@ -0,0 +1,253 @@
+//!
+//! Which IP addresses each sender's messages came from, and when
+//!
+//! Only some messages have an `ip`, but when they do it's useful for spotting someone else
+//! using an account.
+//!
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use maxminddb::{geoip2, Reader};
+use rayon::prelude::*;
+use serde::Serialize;
+
+use super::stream::MessageStream;
+use super::threads::ThreadInfo;
+use super::{selected_threads, Message};
+use crate::source::Export;
+use crate::{ActivityMessages, MagicError};
+
+/// Everything seen from one sender at one IP
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct IpSighting {
+    pub sender: String,
+    pub ip: IpAddr,
+    pub first_seen: Option<DateTime<Utc>>,
+    pub last_seen: Option<DateTime<Utc>>,
+    pub count: usize,
+    pub country: Option<String>,
+    pub asn: Option<u32>,
+    pub as_organization: Option<String>,
+}
+
+/// Like `min`, except a missing time doesn't win
+fn earliest(a: Option<DateTime<Utc>>, b: Option<DateTime<Utc>>) -> Option<DateTime<Utc>> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+/// Tallies up IPs by sender, sorted by sender then when the IP was first seen
+#[derive(Debug, Default)]
+pub struct IpTimeline {
+    sightings: BTreeMap<(String, IpAddr), IpSighting>,
+}
+
+impl IpTimeline {
+    pub fn add(&mut self, message: &Message) {
+        let ip = match message.ip {
+            Some(ip) => ip,
+            None => return,
+        };
+        let seen = DateTime::<Utc>::from_timestamp_millis(message.timestamp_ms as i64);
+        let sighting = self
+            .sightings
+            .entry((message.sender_name.clone(), ip))
+            .or_insert_with(|| IpSighting {
+                sender: message.sender_name.clone(),
+                ip,
+                first_seen: seen,
+                last_seen: seen,
+                count: 0,
+                country: None,
+                asn: None,
+                as_organization: None,
+            });
+        sighting.count += 1;
+        sighting.first_seen = earliest(sighting.first_seen, seen);
+        sighting.last_seen = sighting.last_seen.max(seen);
+    }
+
+    pub fn merge(&mut self, other: IpTimeline) {
+        for (key, theirs) in other.sightings {
+            match self.sightings.get_mut(&key) {
+                Some(ours) => {
+                    ours.count += theirs.count;
+                    ours.first_seen = earliest(ours.first_seen, theirs.first_seen);
+                    ours.last_seen = ours.last_seen.max(theirs.last_seen);
+                }
+                None => {
+                    self.sightings.insert(key, theirs);
+                }
+            }
+        }
+    }
+
+    pub fn into_sightings(self) -> Vec<IpSighting> {
+        let mut sightings: Vec<IpSighting> = self.sightings.into_values().collect();
+        sightings.sort_by(|a, b| {
+            a.sender
+                .cmp(&b.sender)
+                .then_with(|| a.first_seen.cmp(&b.first_seen))
+                .then_with(|| a.ip.cmp(&b.ip))
+        });
+        sightings
+    }
+}
+
+/// Looks IPs up in MaxMind format databases, eg GeoLite2-Country and GeoLite2-ASN. Whatever
+/// each database knows gets filled in, so a country and an ASN database can be used together.
+pub struct GeoIp {
+    readers: Vec<Reader<Vec<u8>>>,
+}
+
+impl GeoIp {
+    pub fn open(paths: &[PathBuf]) -> Result<Self, MagicError> {
+        let readers = paths
+            .iter()
+            .map(|path| {
+                Reader::open_readfile(path).map_err(|err| {
+                    MagicError::Generic(format!("Failed to open {}: {}", path.display(), err))
+                })
+            })
+            .collect::<Result<Vec<_>, MagicError>>()?;
+        Ok(Self { readers })
+    }
+
+    pub fn enrich(&self, sighting: &mut IpSighting) {
+        for reader in self.readers.iter() {
+            if let Ok(country) = reader.lookup::<geoip2::Country>(sighting.ip) {
+                if let Some(iso_code) = country.country.and_then(|c| c.iso_code) {
+                    sighting.country = Some(iso_code.to_string());
+                }
+            }
+            if let Ok(asn) = reader.lookup::<geoip2::Asn>(sighting.ip) {
+                sighting.asn = sighting.asn.or(asn.autonomous_system_number);
+                if let Some(organization) = asn.autonomous_system_organization {
+                    sighting.as_organization = Some(organization.to_string());
+                }
+            }
+        }
+    }
+}
+
+pub fn find_ips(export: &Export, threads: &[ThreadInfo]) -> Result<IpTimeline, MagicError> {
+    threads
+        .par_iter()
+        .map(|thread| {
+            let mut timeline = IpTimeline::default();
+            for message in MessageStream::new(export, &thread.folder) {
+                timeline.add(&message?);
+            }
+            Ok(timeline)
+        })
+        .try_reduce(IpTimeline::default, |mut a, b| {
+            a.merge(b);
+            Ok(a)
+        })
+}
+
+fn write_csv(sightings: &[IpSighting], path: &Path) -> Result<(), MagicError> {
+    let file = File::create(path).map_err(|err| {
+        MagicError::Generic(format!("Failed to create {}: {}", path.display(), err))
+    })?;
+    let mut writer = csv::Writer::from_writer(file);
+    for sighting in sightings {
+        writer
+            .serialize(sighting)
+            .map_err(|err| MagicError::Generic(err.to_string()))?;
+    }
+    writer
+        .flush()
+        .map_err(|err| MagicError::Generic(err.to_string()))
+}
+
+/// Shows the IPs per sender for the thread given on the command line, or every thread
+pub fn ip_timeline(
+    export: &Export,
+    msg: ActivityMessages,
+    geoip_db: Vec<PathBuf>,
+    csv: Option<PathBuf>,
+) -> Result<(), MagicError> {
+    let geoip = GeoIp::open(&geoip_db)?;
+    let threads = selected_threads(export, &msg)?;
+
+    let mut sightings = find_ips(export, &threads)?.into_sightings();
+    for sighting in sightings.iter_mut() {
+        geoip.enrich(sighting);
+    }
+
+    let format_time =
+        |time: Option<DateTime<Utc>>| time.map(|time| time.to_rfc3339()).unwrap_or_default();
+    for sighting in sightings.iter() {
+        let mut location = Vec::new();
+        if let Some(country) = &sighting.country {
+            location.push(country.clone());
+        }
+        if let Some(asn) = sighting.asn {
+            location.push(format!("AS{}", asn));
+        }
+        if let Some(organization) = &sighting.as_organization {
+            location.push(organization.clone());
+        }
+        println!(
+            "{} {} {}x {} - {} {}",
+            sighting.sender,
+            sighting.ip,
+            sighting.count,
+            format_time(sighting.first_seen),
+            format_time(sighting.last_seen),
+            location.join(" ")
+        );
+    }
+    println!("Total: {} distinct sender and IP pairs", sightings.len());
+
+    if let Some(path) = csv {
+        write_csv(&sightings, &path)?;
+        println!("Wrote {} rows to {}", sightings.len(), path.display());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IpTimeline;
+    use serde_json::json;
+
+    use crate::test_util::message;
+
+    #[test]
+    fn test_ip_timeline() {
+        let mut timeline = IpTimeline::default();
+        timeline.add(&message(
+            json!({"sender_name": "Bob", "timestamp_ms": 3000, "ip": "192.0.2.1"}),
+        ));
+        timeline.add(&message(
+            json!({"sender_name": "Bob", "timestamp_ms": 1000, "ip": "192.0.2.1"}),
+        ));
+        timeline.add(&message(
+            json!({"sender_name": "Bob", "timestamp_ms": 2000}),
+        ));
+
+        let mut other = IpTimeline::default();
+        other.add(&message(
+            json!({"sender_name": "Bob", "timestamp_ms": 5000, "ip": "2001:db8::1"}),
+        ));
+        other.add(&message(
+            json!({"sender_name": "Bob", "timestamp_ms": 4000, "ip": "192.0.2.1"}),
+        ));
+        timeline.merge(other);
+
+        let sightings = timeline.into_sightings();
+        assert_eq!(sightings.len(), 2);
+        assert_eq!(sightings[0].ip.to_string(), "192.0.2.1");
+        assert_eq!(sightings[0].count, 3);
+        assert_eq!(sightings[0].first_seen.unwrap().timestamp_millis(), 1000);
+        assert_eq!(sightings[0].last_seen.unwrap().timestamp_millis(), 4000);
+        assert_eq!(sightings[1].ip.to_string(), "2001:db8::1");
+    }
+}
@@ -0,0 +1,432 @@
+//!
+//! Working out what sort of message something is, from which of its optional fields are set
+//!
+//! Messenger doesn't flag system notices ("Alice added Bob to the group.") any differently to
+//! what people typed, so they're picked out by matching the text.
+//!
+use std::sync::LazyLock;
+
+use regex::{Captures, Regex};
+use serde::Serialize;
+
+use super::Message;
+
+/// Something that happened to a thread, rather than something someone said in it
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum SystemEvent {
+    GroupCreated {
+        actor: String,
+    },
+    ParticipantsAdded {
+        actor: String,
+        added: Vec<String>,
+    },
+    ParticipantRemoved {
+        actor: String,
+        removed: String,
+    },
+    ParticipantLeft {
+        actor: String,
+    },
+    GroupNamed {
+        actor: String,
+        name: String,
+    },
+    GroupNameRemoved {
+        actor: String,
+    },
+    GroupPhotoChanged {
+        actor: String,
+    },
+    NicknameSet {
+        actor: String,
+        target: String,
+        nickname: String,
+    },
+    NicknameCleared {
+        actor: String,
+        target: String,
+    },
+    ThemeChanged {
+        actor: String,
+        theme: String,
+    },
+    EmojiChanged {
+        actor: String,
+        emoji: String,
+    },
+    AdminAdded {
+        actor: String,
+        target: String,
+    },
+    AdminRemoved {
+        actor: String,
+        target: String,
+    },
+    CallStarted {
+        actor: String,
+    },
+    CallJoined {
+        actor: String,
+    },
+}
+
+/// What a message is, the first match wins so a photo with a caption is a photo
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MessageKind {
+    Unsent,
+    System(SystemEvent),
+    Call {
+        duration: u64,
+    },
+    MissedCall,
+    Sticker,
+    Photo,
+    Gif,
+    Video,
+    Audio,
+    File,
+    Share,
+    Text,
+    /// None of the fields that'd say what it is are set
+    Empty,
+}
+
+type EventBuilder = fn(&Captures) -> SystemEvent;
+
+fn capture(captures: &Captures, name: &str) -> String {
+    captures
+        .name(name)
+        .map(|m| m.as_str().to_string())
+        .unwrap_or_default()
+}
+
+/// Splits "Alice, Bob and Carol" into names
+fn split_names(names: &str) -> Vec<String> {
+    names
+        .split(", ")
+        .flat_map(|name| name.split(" and "))
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+static SYSTEM_PATTERNS: LazyLock<Vec<(Regex, EventBuilder)>> = LazyLock::new(|| {
+    let patterns: Vec<(&str, EventBuilder)> = vec![
+        (r"^(?P<actor>.+) created the group\.?$", |c| {
+            SystemEvent::GroupCreated {
+                actor: capture(c, "actor"),
+            }
+        }),
+        (
+            r"^(?P<actor>.+?) added (?P<added>.+) to the group\.?$",
+            |c| SystemEvent::ParticipantsAdded {
+                actor: capture(c, "actor"),
+                added: split_names(&capture(c, "added")),
+            },
+        ),
+        (
+            r"^(?P<actor>.+?) removed (?P<removed>.+) from the group\.?$",
+            |c| SystemEvent::ParticipantRemoved {
+                actor: capture(c, "actor"),
+                removed: capture(c, "removed"),
+            },
+        ),
+        (r"^(?P<actor>.+) left the group\.?$", |c| {
+            SystemEvent::ParticipantLeft {
+                actor: capture(c, "actor"),
+            }
+        }),
+        (r"^(?P<actor>.+?) named the group (?P<name>.+?)\.?$", |c| {
+            SystemEvent::GroupNamed {
+                actor: capture(c, "actor"),
+                name: capture(c, "name"),
+            }
+        }),
+        (r"^(?P<actor>.+) removed the group name\.?$", |c| {
+            SystemEvent::GroupNameRemoved {
+                actor: capture(c, "actor"),
+            }
+        }),
+        (r"^(?P<actor>.+) changed the group photo\.?$", |c| {
+            SystemEvent::GroupPhotoChanged {
+                actor: capture(c, "actor"),
+            }
+        }),
+        (
+            r"^(?P<actor>.+?) set (?:the nickname for (?P<target>.+?)|(?P<own>your|his own|her own|their own) nickname) to (?P<nickname>.+?)\.?$",
+            |c| SystemEvent::NicknameSet {
+                actor: capture(c, "actor"),
+                target: match c.name("target") {
+                    Some(target) => target.as_str().to_string(),
+                    None if capture(c, "own") == "your" => "You".to_string(),
+                    None => capture(c, "actor"),
+                },
+                nickname: capture(c, "nickname"),
+            },
+        ),
+        (
+            r"^(?P<actor>.+?) cleared the nickname for (?P<target>.+?)\.?$",
+            |c| SystemEvent::NicknameCleared {
+                actor: capture(c, "actor"),
+                target: capture(c, "target"),
+            },
+        ),
+        (
+            r"^(?P<actor>.+?) changed the (?:chat )?theme to (?P<theme>.+?)\.?$",
+            |c| SystemEvent::ThemeChanged {
+                actor: capture(c, "actor"),
+                theme: capture(c, "theme"),
+            },
+        ),
+        (
+            r"^(?P<actor>.+?) (?:set|changed) the (?:chat )?emoji to (?P<emoji>.+?)\.?$",
+            |c| SystemEvent::EmojiChanged {
+                actor: capture(c, "actor"),
+                emoji: capture(c, "emoji"),
+            },
+        ),
+        (r"^(?P<actor>.+?) made (?P<target>.+?) an admin\.?$", |c| {
+            SystemEvent::AdminAdded {
+                actor: capture(c, "actor"),
+                target: capture(c, "target"),
+            }
+        }),
+        (
+            r"^(?P<actor>.+?) removed (?P<target>.+?) as an admin\.?$",
+            |c| SystemEvent::AdminRemoved {
+                actor: capture(c, "actor"),
+                target: capture(c, "target"),
+            },
+        ),
+        (
+            r"^(?P<actor>.+) started (?:a|an audio|a video) call\.?$",
+            |c| SystemEvent::CallStarted {
+                actor: capture(c, "actor"),
+            },
+        ),
+        (r"^(?P<actor>.+) joined the (?:video )?call\.?$", |c| {
+            SystemEvent::CallJoined {
+                actor: capture(c, "actor"),
+            }
+        }),
+    ];
+    patterns
+        .into_iter()
+        .map(|(pattern, builder)| (Regex::new(pattern).unwrap(), builder))
+        .collect()
+});
+
+fn has_any<T>(list: &Option<Vec<T>>) -> bool {
+    list.as_ref().is_some_and(|list| !list.is_empty())
+}
+
+impl SystemEvent {
+    /// The snake_case name, same as the `event` field in JSON
+    pub fn name(&self) -> &'static str {
+        match self {
+            SystemEvent::GroupCreated { .. } => "group_created",
+            SystemEvent::ParticipantsAdded { .. } => "participants_added",
+            SystemEvent::ParticipantRemoved { .. } => "participant_removed",
+            SystemEvent::ParticipantLeft { .. } => "participant_left",
+            SystemEvent::GroupNamed { .. } => "group_named",
+            SystemEvent::GroupNameRemoved { .. } => "group_name_removed",
+            SystemEvent::GroupPhotoChanged { .. } => "group_photo_changed",
+            SystemEvent::NicknameSet { .. } => "nickname_set",
+            SystemEvent::NicknameCleared { .. } => "nickname_cleared",
+            SystemEvent::ThemeChanged { .. } => "theme_changed",
+            SystemEvent::EmojiChanged { .. } => "emoji_changed",
+            SystemEvent::AdminAdded { .. } => "admin_added",
+            SystemEvent::AdminRemoved { .. } => "admin_removed",
+            SystemEvent::CallStarted { .. } => "call_started",
+            SystemEvent::CallJoined { .. } => "call_joined",
+        }
+    }
+
+    /// Who did it
+    pub fn actor(&self) -> &str {
+        match self {
+            SystemEvent::GroupCreated { actor }
+            | SystemEvent::ParticipantsAdded { actor, .. }
+            | SystemEvent::ParticipantRemoved { actor, .. }
+            | SystemEvent::ParticipantLeft { actor }
+            | SystemEvent::GroupNamed { actor, .. }
+            | SystemEvent::GroupNameRemoved { actor }
+            | SystemEvent::GroupPhotoChanged { actor }
+            | SystemEvent::NicknameSet { actor, .. }
+            | SystemEvent::NicknameCleared { actor, .. }
+            | SystemEvent::ThemeChanged { actor, .. }
+            | SystemEvent::EmojiChanged { actor, .. }
+            | SystemEvent::AdminAdded { actor, .. }
+            | SystemEvent::AdminRemoved { actor, .. }
+            | SystemEvent::CallStarted { actor }
+            | SystemEvent::CallJoined { actor } => actor,
+        }
+    }
+
+    /// Who it was done to, if anyone
+    pub fn targets(&self) -> Vec<&str> {
+        match self {
+            SystemEvent::ParticipantsAdded { added, .. } => {
+                added.iter().map(|name| name.as_str()).collect()
+            }
+            SystemEvent::ParticipantRemoved {
+                removed: target, ..
+            }
+            | SystemEvent::NicknameSet { target, .. }
+            | SystemEvent::NicknameCleared { target, .. }
+            | SystemEvent::AdminAdded { target, .. }
+            | SystemEvent::AdminRemoved { target, .. } => vec![target],
+            _ => Vec::new(),
+        }
+    }
+
+    /// What it was set to, for renames, nicknames, themes and emoji
+    pub fn value(&self) -> Option<&str> {
+        match self {
+            SystemEvent::GroupNamed { name: value, .. }
+            | SystemEvent::NicknameSet {
+                nickname: value, ..
+            }
+            | SystemEvent::ThemeChanged { theme: value, .. }
+            | SystemEvent::EmojiChanged { emoji: value, .. } => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Matches the text against the notices Messenger generates
+    pub fn parse(content: &str) -> Option<Self> {
+        let content = content.trim();
+        SYSTEM_PATTERNS
+            .iter()
+            .find_map(|(regex, builder)| regex.captures(content).map(|captures| builder(&captures)))
+    }
+}
+
+impl MessageKind {
+    /// Short lowercase name, for stats and the like
+    pub fn name(&self) -> &'static str {
+        match self {
+            MessageKind::Unsent => "unsent",
+            MessageKind::System(_) => "group notice",
+            MessageKind::Call { .. } => "call",
+            MessageKind::MissedCall => "missed call",
+            MessageKind::Sticker => "sticker",
+            MessageKind::Photo => "photo",
+            MessageKind::Gif => "gif",
+            MessageKind::Video => "video",
+            MessageKind::Audio => "audio",
+            MessageKind::File => "file",
+            MessageKind::Share => "share",
+            MessageKind::Text => "text",
+            MessageKind::Empty => "empty",
+        }
+    }
+}
+
+impl Message {
+    /// Works out what sort of message this is
+    pub fn kind(&self) -> MessageKind {
+        if self.is_unsent == Some(true) {
+            return MessageKind::Unsent;
+        }
+        if let Some(duration) = self.call_duration {
+            if self.missed == Some(true) || duration == 0 {
+                return MessageKind::MissedCall;
+            }
+            return MessageKind::Call { duration };
+        }
+        if self.attachment_uris().is_empty() && self.share.is_none() {
+            if let Some(event) = self.content.as_deref().and_then(SystemEvent::parse) {
+                return MessageKind::System(event);
+            }
+        }
+        if self.sticker.is_some() {
+            MessageKind::Sticker
+        } else if has_any(&self.photos) {
+            MessageKind::Photo
+        } else if has_any(&self.gifs) {
+            MessageKind::Gif
+        } else if has_any(&self.videos) {
+            MessageKind::Video
+        } else if has_any(&self.audio_files) {
+            MessageKind::Audio
+        } else if has_any(&self.files) {
+            MessageKind::File
+        } else if self.share.is_some() {
+            MessageKind::Share
+        } else if self.content.as_deref().is_some_and(|c| !c.is_empty()) {
+            MessageKind::Text
+        } else {
+            MessageKind::Empty
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MessageKind, SystemEvent};
+    use crate::test_util::message;
+
+    #[test]
+    fn test_system_events() {
+        assert_eq!(
+            SystemEvent::parse("Alice added Bob, Carol and Dave to the group."),
+            Some(SystemEvent::ParticipantsAdded {
+                actor: "Alice".to_string(),
+                added: vec!["Bob".to_string(), "Carol".to_string(), "Dave".to_string()],
+            })
+        );
+        assert_eq!(
+            SystemEvent::parse("Alice named the group Book Club."),
+            Some(SystemEvent::GroupNamed {
+                actor: "Alice".to_string(),
+                name: "Book Club".to_string(),
+            })
+        );
+        assert_eq!(
+            SystemEvent::parse("Alice set your nickname to Bobby."),
+            Some(SystemEvent::NicknameSet {
+                actor: "Alice".to_string(),
+                target: "You".to_string(),
+                nickname: "Bobby".to_string(),
+            })
+        );
+        assert_eq!(SystemEvent::parse("see you at the group thing"), None);
+    }
+
+    #[test]
+    fn test_message_kind() {
+        let kind = |value| message(value).kind();
+        assert_eq!(
+            kind(serde_json::json!({"content": "hi"})),
+            MessageKind::Text
+        );
+        assert_eq!(
+            kind(serde_json::json!({"content": "Alice left the group."})),
+            MessageKind::System(SystemEvent::ParticipantLeft {
+                actor: "Alice".to_string()
+            })
+        );
+        assert_eq!(
+            kind(
+                serde_json::json!({"content": "Alice left the group.", "photos": [{"uri": "1.jpg"}]})
+            ),
+            MessageKind::Photo
+        );
+        assert_eq!(
+            kind(serde_json::json!({"call_duration": 0, "content": "Bob missed your call."})),
+            MessageKind::MissedCall
+        );
+        assert_eq!(
+            kind(serde_json::json!({"call_duration": 65})),
+            MessageKind::Call { duration: 65 }
+        );
+        assert_eq!(
+            kind(serde_json::json!({"is_unsent": true})),
+            MessageKind::Unsent
+        );
+        assert_eq!(kind(serde_json::json!({})), MessageKind::Empty);
+    }
+}
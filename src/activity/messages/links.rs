@@ -0,0 +1,356 @@
+//!
+//! Links people have shared, both as shares and pasted into messages
+//!
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::LazyLock;
+
+use chrono::{DateTime, Utc};
+use rayon::prelude::*;
+use regex::Regex;
+use serde::Serialize;
+use url::Url;
+
+use super::stream::MessageStream;
+use super::threads::ThreadInfo;
+use super::{selected_threads, Message};
+use crate::source::Export;
+use crate::{ActivityMessages, LinkFormat, MagicError};
+
+static URL_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"https?://[^\s<>"]+"#).unwrap());
+
+/// Query parameters that are only there to track who clicked
+static TRACKING_PARAMS: &[&str] = &[
+    "fbclid", "gclid", "dclid", "msclkid", "yclid", "igshid", "mc_cid", "mc_eid", "_ga", "ref_src",
+];
+
+/// Undoes Facebook's redirect wrapper and drops tracking parameters. `None` if it's not a URL.
+pub fn normalize_url(link: &str) -> Option<Url> {
+    let mut url = Url::parse(link.trim()).ok()?;
+    if matches!(url.host_str(), Some("l.facebook.com" | "lm.facebook.com"))
+        && url.path() == "/l.php"
+    {
+        let target = url
+            .query_pairs()
+            .find(|(key, _)| key == "u")
+            .map(|(_, value)| value.into_owned())?;
+        url = Url::parse(&target).ok()?;
+    }
+    if !matches!(url.scheme(), "http" | "https") {
+        return None;
+    }
+
+    let params: Vec<(String, String)> = url
+        .query_pairs()
+        .filter(|(key, _)| !key.starts_with("utm_") && !TRACKING_PARAMS.contains(&key.as_ref()))
+        .map(|(key, value)| (key.into_owned(), value.into_owned()))
+        .collect();
+    if params.is_empty() {
+        url.set_query(None);
+    } else {
+        url.query_pairs_mut().clear().extend_pairs(params);
+    }
+    Some(url)
+}
+
+/// The links in a message, the shared one first then any in the text
+pub fn message_links(message: &Message) -> Vec<String> {
+    let mut links = Vec::new();
+    if let Some(link) = message.share.as_ref().and_then(|share| share.link.as_ref()) {
+        links.push(link.clone());
+    }
+    if let Some(content) = message.content.as_deref() {
+        for found in URL_REGEX.find_iter(content) {
+            let link = found
+                .as_str()
+                .trim_end_matches(['.', ',', ';', ':', '!', '?', ')', '\'']);
+            if !links.iter().any(|l| l == link) {
+                links.push(link.to_string());
+            }
+        }
+    }
+    links
+}
+
+/// One time a link was shared
+#[derive(Serialize, Debug, Clone)]
+pub struct SharedLink {
+    pub url: String,
+    pub original: String,
+    pub sender: String,
+    pub thread: PathBuf,
+    pub timestamp: Option<DateTime<Utc>>,
+    pub share_text: Option<String>,
+}
+
+/// A link and everywhere it was shared
+#[derive(Serialize, Debug, Clone)]
+pub struct LinkEntry {
+    pub url: String,
+    pub domain: String,
+    pub share_text: Option<String>,
+    pub first_shared: Option<DateTime<Utc>>,
+    pub last_shared: Option<DateTime<Utc>>,
+    pub count: usize,
+    pub senders: BTreeSet<String>,
+    pub threads: BTreeSet<PathBuf>,
+}
+
+pub fn find_links(export: &Export, threads: &[ThreadInfo]) -> Result<Vec<SharedLink>, MagicError> {
+    let per_thread = threads
+        .par_iter()
+        .map(|thread| {
+            let mut links = Vec::new();
+            for message in MessageStream::new(export, &thread.folder) {
+                let message = message?;
+                for original in message_links(&message) {
+                    let url = match normalize_url(&original) {
+                        Some(url) => url,
+                        None => continue,
+                    };
+                    links.push(SharedLink {
+                        url: url.to_string(),
+                        sender: message.sender_name.clone(),
+                        thread: thread.folder.clone(),
+                        timestamp: DateTime::<Utc>::from_timestamp_millis(
+                            message.timestamp_ms as i64,
+                        ),
+                        // the share text only goes with the shared link, not ones in the text
+                        share_text: message
+                            .share
+                            .as_ref()
+                            .filter(|share| share.link.as_ref() == Some(&original))
+                            .and_then(|share| share.share_text.clone()),
+                        original,
+                    });
+                }
+            }
+            Ok(links)
+        })
+        .collect::<Result<Vec<Vec<SharedLink>>, MagicError>>()?;
+    Ok(per_thread.into_iter().flatten().collect())
+}
+
+/// Collapses shares of the same URL into one entry, sorted by URL
+pub fn dedupe_links(links: &[SharedLink]) -> Vec<LinkEntry> {
+    let mut entries: BTreeMap<&str, LinkEntry> = BTreeMap::new();
+    for link in links {
+        let entry = entries.entry(&link.url).or_insert_with(|| LinkEntry {
+            url: link.url.clone(),
+            domain: Url::parse(&link.url)
+                .ok()
+                .and_then(|url| url.host_str().map(|host| host.to_string()))
+                .unwrap_or_default(),
+            share_text: None,
+            first_shared: link.timestamp,
+            last_shared: link.timestamp,
+            count: 0,
+            senders: BTreeSet::new(),
+            threads: BTreeSet::new(),
+        });
+        entry.count += 1;
+        entry.first_shared = match (entry.first_shared, link.timestamp) {
+            (Some(first), Some(timestamp)) => Some(first.min(timestamp)),
+            (first, timestamp) => first.or(timestamp),
+        };
+        entry.last_shared = entry.last_shared.max(link.timestamp);
+        if entry.share_text.is_none() {
+            entry.share_text = link.share_text.clone();
+        }
+        entry.senders.insert(link.sender.clone());
+        entry.threads.insert(link.thread.clone());
+    }
+    entries.into_values().collect()
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct DomainStats {
+    pub shares: usize,
+    pub urls: usize,
+}
+
+pub fn domain_stats(entries: &[LinkEntry]) -> BTreeMap<String, DomainStats> {
+    let mut domains: BTreeMap<String, DomainStats> = BTreeMap::new();
+    for entry in entries {
+        let stats = domains.entry(entry.domain.clone()).or_default();
+        stats.shares += entry.count;
+        stats.urls += 1;
+    }
+    domains
+}
+
+#[derive(Serialize)]
+struct LinkRow<'a> {
+    url: &'a str,
+    domain: &'a str,
+    share_text: &'a str,
+    first_shared: String,
+    last_shared: String,
+    count: usize,
+    senders: String,
+    threads: String,
+}
+
+fn format_time(time: Option<DateTime<Utc>>) -> String {
+    time.map(|time| time.to_rfc3339()).unwrap_or_default()
+}
+
+fn write_csv(entries: &[LinkEntry], writer: impl Write) -> Result<(), MagicError> {
+    let mut writer = csv::Writer::from_writer(writer);
+    for entry in entries {
+        writer
+            .serialize(LinkRow {
+                url: &entry.url,
+                domain: &entry.domain,
+                share_text: entry.share_text.as_deref().unwrap_or_default(),
+                first_shared: format_time(entry.first_shared),
+                last_shared: format_time(entry.last_shared),
+                count: entry.count,
+                senders: entry.senders.iter().cloned().collect::<Vec<_>>().join("; "),
+                threads: entry
+                    .threads
+                    .iter()
+                    .map(|thread| thread.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join("; "),
+            })
+            .map_err(|err| MagicError::Generic(err.to_string()))?;
+    }
+    writer
+        .flush()
+        .map_err(|err| MagicError::Generic(err.to_string()))
+}
+
+pub(crate) fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// The Netscape bookmark file format, which every browser can import
+pub fn write_bookmarks(entries: &[LinkEntry], mut writer: impl Write) -> Result<(), MagicError> {
+    let mut html = String::from(
+        "<!DOCTYPE NETSCAPE-Bookmark-file-1>\n\
+         <META HTTP-EQUIV=\"Content-Type\" CONTENT=\"text/html; charset=UTF-8\">\n\
+         <TITLE>Bookmarks</TITLE>\n\
+         <H1>Shared in Messenger</H1>\n\
+         <DL><p>\n",
+    );
+    for entry in entries {
+        html.push_str(&format!(
+            "    <DT><A HREF=\"{}\" ADD_DATE=\"{}\">{}</A>\n",
+            html_escape(&entry.url),
+            entry
+                .first_shared
+                .map(|time| time.timestamp())
+                .unwrap_or_default(),
+            html_escape(entry.share_text.as_deref().unwrap_or(&entry.url))
+        ));
+    }
+    html.push_str("</DL><p>\n");
+    writer
+        .write_all(html.as_bytes())
+        .map_err(|err| MagicError::Generic(err.to_string()))
+}
+
+/// Collects the links in the thread given on the command line, or every thread if there isn't
+/// one. Writes them out if there's a format, and shows per-domain stats.
+pub fn list_links(
+    export: &Export,
+    msg: ActivityMessages,
+    format: Option<LinkFormat>,
+    output: Option<PathBuf>,
+) -> Result<(), MagicError> {
+    let threads = selected_threads(export, &msg)?;
+    let links = find_links(export, &threads)?;
+    let entries = dedupe_links(&links);
+
+    if let Some(format) = format {
+        let writer: Box<dyn Write> = match &output {
+            Some(path) => {
+                Box::new(File::create(path).map_err(|err| MagicError::Generic(err.to_string()))?)
+            }
+            None => Box::new(std::io::stdout()),
+        };
+        match format {
+            LinkFormat::Json => serde_json::to_writer_pretty(writer, &entries)
+                .map_err(|err| MagicError::Generic(err.to_string()))?,
+            LinkFormat::Csv => write_csv(&entries, writer)?,
+            LinkFormat::Bookmarks => write_bookmarks(&entries, writer)?,
+        }
+        if output.is_none() {
+            // the links went to stdout, so don't mix the stats in with them
+            return Ok(());
+        }
+    }
+
+    let mut domains: Vec<(String, DomainStats)> = domain_stats(&entries).into_iter().collect();
+    domains.sort_by(|a, b| b.1.shares.cmp(&a.1.shares).then_with(|| a.0.cmp(&b.0)));
+    for (domain, stats) in domains.iter() {
+        println!(
+            "{}: {} shares, {} unique links",
+            domain, stats.shares, stats.urls
+        );
+    }
+    println!(
+        "Total: {} shares of {} unique links from {} domains",
+        links.len(),
+        entries.len(),
+        domains.len()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{dedupe_links, domain_stats, normalize_url, write_bookmarks, SharedLink};
+
+    #[test]
+    fn test_normalize_url() {
+        assert_eq!(
+            normalize_url("https://l.facebook.com/l.php?u=https%3A%2F%2Fexample.com%2Fpost%3Fid%3D1%26utm_source%3Dfb&h=AT0")
+                .unwrap()
+                .as_str(),
+            "https://example.com/post?id=1"
+        );
+        assert_eq!(
+            normalize_url("https://Example.com/a?fbclid=123")
+                .unwrap()
+                .as_str(),
+            "https://example.com/a"
+        );
+        assert!(normalize_url("not a link").is_none());
+    }
+
+    #[test]
+    fn test_dedupe_links() {
+        let link = |sender: &str, url: &str| SharedLink {
+            url: url.to_string(),
+            original: url.to_string(),
+            sender: sender.to_string(),
+            thread: PathBuf::from("inbox/bob_1"),
+            timestamp: None,
+            share_text: Some("A <b>story</b>".to_string()),
+        };
+        let entries = dedupe_links(&[
+            link("Bob", "https://example.com/a"),
+            link("Alice", "https://example.com/a"),
+            link("Bob", "https://example.org/"),
+        ]);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].count, 2);
+        assert_eq!(entries[0].senders.len(), 2);
+        assert_eq!(domain_stats(&entries)["example.com"].shares, 2);
+
+        let mut html = Vec::new();
+        write_bookmarks(&entries, &mut html).unwrap();
+        assert!(String::from_utf8(html).unwrap().contains(
+            "<A HREF=\"https://example.com/a\" ADD_DATE=\"0\">A &lt;b&gt;story&lt;/b&gt;</A>"
+        ));
+    }
+}
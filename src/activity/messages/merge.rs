@@ -0,0 +1,153 @@
+//!
+//! Merging several exports of the same account, so messages deleted since an older download
+//! aren't lost and the ones in both only show up once
+//!
+use std::collections::{BTreeMap, HashSet};
+use std::path::PathBuf;
+
+use super::id::MessageId;
+use super::stream::stream_message_file;
+use super::{find_message_files, thread_path_from_file, Message};
+use crate::activity::ActivityTypes;
+use crate::source::Export;
+use crate::{MagicError, Skippable};
+
+/// Drops messages that have already been seen.
+///
+/// Messages are handled a thread at a time, so only the current thread's IDs are kept.
+#[derive(Debug, Default)]
+pub struct Deduplicator {
+    thread: Option<String>,
+    seen: HashSet<MessageId>,
+}
+
+impl Deduplicator {
+    /// Returns true the first time a message is seen
+    pub fn is_new(&mut self, thread: &str, message: &Message) -> bool {
+        if self.thread.as_deref() != Some(thread) {
+            self.thread = Some(thread.to_string());
+            self.seen.clear();
+        }
+        self.seen.insert(MessageId::for_message(thread, message))
+    }
+}
+
+/// Pairs each message file with every export that has a copy of it, newest first
+pub fn message_file_copies(export: &Export, files: Vec<PathBuf>) -> Vec<(PathBuf, Export)> {
+    let exports = export.exports();
+    files
+        .into_iter()
+        .flat_map(|file| {
+            exports
+                .iter()
+                .filter(|source| source.exists(&file))
+                .map(|source| (file.clone(), source.clone()))
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+#[derive(Debug, Default)]
+struct ThreadSummary {
+    /// unique messages, by the export they were first found in
+    per_export: Vec<usize>,
+    duplicates: usize,
+}
+
+/// Show what merging the exports gives, per thread
+pub fn merge_summary(export: &Export) -> Result<(), MagicError> {
+    let exports = export.exports();
+    println!("Merging {} exports", exports.len());
+
+    let mut threads: BTreeMap<String, ThreadSummary> = BTreeMap::new();
+    let files = find_message_files(export, &ActivityTypes::Messages.path());
+    let mut dedup = Deduplicator::default();
+    for file in files {
+        let thread = thread_path_from_file(&file);
+        for (index, source) in exports.iter().enumerate() {
+            if !source.exists(&file) {
+                continue;
+            }
+            let summary = threads
+                .entry(thread.clone())
+                .or_insert_with(|| ThreadSummary {
+                    per_export: vec![0; exports.len()],
+                    duplicates: 0,
+                });
+            stream_message_file(source, &file, |message| {
+                if dedup.is_new(&thread, &message) {
+                    summary.per_export[index] += 1;
+                } else {
+                    summary.duplicates += 1;
+                }
+                Ok(())
+            })?;
+        }
+    }
+
+    for (thread, summary) in threads.iter() {
+        let total: usize = summary.per_export.iter().sum();
+        println!(
+            "{}: {} messages, {} only in older exports, {} duplicates dropped",
+            thread,
+            total,
+            total - summary.per_export[0],
+            summary.duplicates
+        );
+    }
+    let total: usize = threads.values().flat_map(|s| s.per_export.iter()).sum();
+    let recovered: usize = threads
+        .values()
+        .flat_map(|s| s.per_export.iter().skip(1))
+        .sum();
+    println!(
+        "Total: {} threads, {} messages, {} only in older exports",
+        threads.len(),
+        total,
+        recovered
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Deduplicator;
+    use serde_json::json;
+
+    use crate::activity::messages::Message;
+    use crate::test_util::message;
+
+    #[test]
+    fn test_deduplicator() {
+        let mut dedup = Deduplicator::default();
+        assert!(dedup.is_new(
+            "inbox/bob_1",
+            &message(json!({"sender_name": "Bob", "content": "hi"}))
+        ));
+        assert!(!dedup.is_new(
+            "inbox/bob_1",
+            &message(json!({"sender_name": "Bob", "content": "hi"}))
+        ));
+        assert!(dedup.is_new(
+            "inbox/bob_1",
+            &message(json!({"sender_name": "Bob", "content": "hi again"}))
+        ));
+        assert!(dedup.is_new(
+            "inbox/alice_2",
+            &message(json!({"sender_name": "Bob", "content": "hi"}))
+        ));
+
+        // the same photo from exports with different layouts
+        let photo = |uri: &str| -> Message {
+            message(json!({"sender_name": "Bob", "timestamp_ms": 2, "photos": [{"uri": uri}]}))
+        };
+        assert!(dedup.is_new(
+            "inbox/alice_2",
+            &photo("messages/inbox/alice_2/photos/1.jpg")
+        ));
+        assert!(!dedup.is_new(
+            "inbox/alice_2",
+            &photo("your_activity_across_facebook/messages/inbox/alice_2/photos/1.jpg")
+        ));
+    }
+}
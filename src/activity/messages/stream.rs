@@ -0,0 +1,370 @@
+//!
+//! Streaming message parsing, so huge threads don't have to fit in memory
+//!
+use std::fmt;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{sync_channel, Receiver};
+use std::thread::JoinHandle;
+
+use serde::de::{DeserializeSeed, Error, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde::Deserializer;
+use serde_json::{Map, Value};
+
+use super::e2ee::{E2eeMessage, E2eeMessageFileParser, MessageFileFormat};
+use super::html::{is_html_file, parse_html_message_file};
+use super::merge::{message_file_copies, Deduplicator};
+use super::{find_message_files, thread_path_from_file, Message, MessageFileParser};
+use crate::source::Export;
+use crate::MagicError;
+
+/// How many parsed messages can be waiting in the channel before the parser blocks
+static STREAM_BUFFER: usize = 1024;
+
+/// Walks the top-level object of a message file, handing each message to the callback as it's
+/// parsed and keeping everything else as the header.
+struct FileVisitor<'a, F> {
+    on_message: &'a mut F,
+    skip_messages: bool,
+    path: &'a Path,
+}
+
+impl<'de, F> Visitor<'de> for FileVisitor<'_, F>
+where
+    F: FnMut(Message) -> Result<(), MagicError>,
+{
+    type Value = (Map<String, Value>, MessageFileFormat);
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a message file object")
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let mut header = Map::new();
+        let mut format = None;
+        while let Some(key) = map.next_key::<String>()? {
+            if key == "messages" && self.skip_messages {
+                map.next_value::<IgnoredAny>()?;
+                header.insert(key, Value::Array(Vec::new()));
+            } else if key == "messages" {
+                // participants usually come first so the format's known by now, if not the
+                // messages themselves give it away
+                let found = map.next_value_seed(MessagesSeed {
+                    on_message: &mut *self.on_message,
+                    format,
+                    thread_folder: self.path.parent().unwrap_or(Path::new("")),
+                })?;
+                format = format.or(found);
+                header.insert(key, Value::Array(Vec::new()));
+            } else {
+                let value: Value = map.next_value()?;
+                if format.is_none() {
+                    format = MessageFileFormat::detect(&key, &value);
+                }
+                header.insert(key, value);
+            }
+        }
+        Ok((header, format.unwrap_or_default()))
+    }
+}
+
+/// Deserializes the `messages` array one element at a time. If the format isn't known yet it's
+/// worked out from the first message, and handed back.
+struct MessagesSeed<'a, F> {
+    on_message: &'a mut F,
+    format: Option<MessageFileFormat>,
+    thread_folder: &'a Path,
+}
+
+impl<'de, F> DeserializeSeed<'de> for MessagesSeed<'_, F>
+where
+    F: FnMut(Message) -> Result<(), MagicError>,
+{
+    type Value = Option<MessageFileFormat>;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, F> Visitor<'de> for MessagesSeed<'_, F>
+where
+    F: FnMut(Message) -> Result<(), MagicError>,
+{
+    type Value = Option<MessageFileFormat>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an array of messages")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let mut format = self.format;
+        loop {
+            let message = match format {
+                Some(MessageFileFormat::Legacy) => seq.next_element::<Message>()?,
+                Some(MessageFileFormat::E2ee) => seq
+                    .next_element::<E2eeMessage>()?
+                    .map(|message| message.into_message(self.thread_folder)),
+                None => match seq.next_element::<Value>()? {
+                    Some(value) => {
+                        let detected =
+                            MessageFileFormat::detect_message(&value).unwrap_or_default();
+                        format = Some(detected);
+                        Some(match detected {
+                            MessageFileFormat::Legacy => serde_json::from_value::<Message>(value)
+                                .map_err(A::Error::custom)?,
+                            MessageFileFormat::E2ee => serde_json::from_value::<E2eeMessage>(value)
+                                .map_err(A::Error::custom)?
+                                .into_message(self.thread_folder),
+                        })
+                    }
+                    None => None,
+                },
+            };
+            match message {
+                Some(message) => (self.on_message)(message)
+                    .map_err(|err| A::Error::custom(format!("{:?}", err)))?,
+                None => return Ok(format),
+            }
+        }
+    }
+}
+
+/// Parses a JSON message file in either format, turning the header into a [MessageFileParser]
+/// once the messages have been handed off.
+fn parse_json<R, F>(
+    reader: R,
+    path: &Path,
+    mut on_message: F,
+    skip_messages: bool,
+) -> Result<MessageFileParser, MagicError>
+where
+    R: Read,
+    F: FnMut(Message) -> Result<(), MagicError>,
+{
+    let mut deserializer = serde_json::Deserializer::from_reader(reader);
+    let (header, format) = deserializer
+        .deserialize_map(FileVisitor {
+            on_message: &mut on_message,
+            skip_messages,
+            path,
+        })
+        .and_then(|header| deserializer.end().map(|_| header))
+        .map_err(|err| MagicError::Generic(format!("{}: {}", path.display(), err)))?;
+
+    match format {
+        MessageFileFormat::Legacy => serde_json::from_value(Value::Object(header)),
+        MessageFileFormat::E2ee => {
+            serde_json::from_value::<E2eeMessageFileParser>(Value::Object(header))
+                .map(|parsed| parsed.into_message_file(path))
+        }
+    }
+    .map_err(|err| MagicError::Generic(format!("{}: {}", path.display(), err)))
+}
+
+/// Parses a message file, calling `on_message` for each message as it's read instead of
+/// collecting them. Returns the rest of the file with an empty `messages` list.
+///
+/// If `on_message` returns an error parsing stops and the error's returned.
+pub fn stream_message_file<F>(
+    export: &Export,
+    path: &Path,
+    on_message: F,
+) -> Result<MessageFileParser, MagicError>
+where
+    F: FnMut(Message) -> Result<(), MagicError>,
+{
+    stream_message_reader(export.open(path)?, path, on_message)
+}
+
+/// The guts of [stream_message_file], `path` is used to pick the format, work out where media
+/// is and for error messages. Messages get their IDs set before they're handed over.
+pub fn stream_message_reader<R, F>(
+    reader: R,
+    path: &Path,
+    mut on_message: F,
+) -> Result<MessageFileParser, MagicError>
+where
+    R: Read,
+    F: FnMut(Message) -> Result<(), MagicError>,
+{
+    let thread = thread_path_from_file(path);
+    let mut on_message = |mut message: Message| {
+        message.assign_ids(&thread);
+        on_message(message)
+    };
+    if is_html_file(path) {
+        // there's no streaming HTML parser, so the page is parsed and then handed over
+        let mut parsed = parse_html_message_file(reader, path)?;
+        for message in std::mem::take(&mut parsed.messages) {
+            on_message(message)?;
+        }
+        return Ok(parsed);
+    }
+    parse_json(reader, path, on_message, false)
+}
+
+/// Parses everything in a message file except the messages, which are skipped over without
+/// being deserialized
+pub fn read_message_header(export: &Export, path: &Path) -> Result<MessageFileParser, MagicError> {
+    let reader = export.open(path)?;
+    if is_html_file(path) {
+        let mut parsed = parse_html_message_file(reader, path)?;
+        parsed.messages.clear();
+        return Ok(parsed);
+    }
+    parse_json(reader, path, |_| Ok(()), true)
+}
+
+/// Yields every message from a set of message files, one at a time.
+///
+/// Files are parsed in order on a background thread, with at most [STREAM_BUFFER] messages
+/// waiting to be consumed, so memory use doesn't depend on the size of the thread. Parsing stops
+/// after the first error, which is yielded as the last item.
+pub struct MessageStream {
+    receiver: Receiver<Result<Message, MagicError>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl MessageStream {
+    /// Stream all the messages in a folder, as found by [find_message_files]
+    pub fn new(export: &Export, folder: &Path) -> Self {
+        Self::from_files(export, find_message_files(export, folder))
+    }
+
+    /// When older exports are merged in, each file's read from every export that has it and
+    /// messages that have already been seen are skipped.
+    pub fn from_files(export: &Export, files: Vec<PathBuf>) -> Self {
+        let merged = export.is_merged();
+        let copies = message_file_copies(export, files);
+        let (sender, receiver) = sync_channel(STREAM_BUFFER);
+        let handle = std::thread::spawn(move || {
+            let mut dedup = Deduplicator::default();
+            for (file, source) in copies {
+                let thread = thread_path_from_file(&file);
+                let result = stream_message_file(&source, &file, |message| {
+                    if merged && !dedup.is_new(&thread, &message) {
+                        return Ok(());
+                    }
+                    sender
+                        .send(Ok(message))
+                        .map_err(|_| MagicError::Generic("Message stream was dropped".to_string()))
+                });
+                if let Err(err) = result {
+                    // if the receiver's gone this fails too, either way we're done
+                    let _ = sender.send(Err(err));
+                    return;
+                }
+            }
+        });
+        Self {
+            receiver,
+            handle: Some(handle),
+        }
+    }
+}
+
+impl Iterator for MessageStream {
+    type Item = Result<Message, MagicError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.receiver.recv() {
+            Ok(item) => Some(item),
+            Err(_) => {
+                if let Some(handle) = self.handle.take() {
+                    if handle.join().is_err() {
+                        return Some(Err(MagicError::Generic(
+                            "Message parser thread panicked".to_string(),
+                        )));
+                    }
+                }
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{stream_message_file, stream_message_reader, MessageStream};
+    use crate::source::{DirectorySource, Export};
+    use crate::test_util::{temp_dir, write_file};
+
+    static TEST_FILE: &str = r#"{
+        "participants": [{"name": "Alice"}, {"name": "Bob"}],
+        "messages": [
+            {"sender_name": "Bob", "timestamp_ms": 1700000002000, "content": "hi!", "is_geoblocked_for_viewer": false},
+            {"sender_name": "Alice", "timestamp_ms": 1700000001000, "content": "hello", "is_geoblocked_for_viewer": false}
+        ],
+        "title": "Bob",
+        "is_still_participant": true,
+        "thread_path": "inbox/bob_123",
+        "magic_words": []
+    }"#;
+
+    #[test]
+    fn test_stream_message_file() {
+        let temp = temp_dir();
+        let folder = temp.path().to_path_buf();
+        write_file(&folder, "message_1.json", TEST_FILE);
+
+        let export = Export::from_source(DirectorySource::new(&folder));
+        let mut senders = Vec::new();
+        let header = stream_message_file(&export, Path::new("message_1.json"), |message| {
+            senders.push(message.sender_name);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(senders, vec!["Bob", "Alice"]);
+        assert_eq!(header.thread_path, "inbox/bob_123");
+        assert!(header.messages.is_empty());
+
+        let streamed: Vec<_> = MessageStream::new(&export, Path::new(""))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(streamed.len(), 2);
+    }
+
+    #[test]
+    fn test_e2ee_messages_first() {
+        let file = r#"{
+            "messages": [
+                {"isUnsent": false, "media": [{"uri": "./media/1.jpg"}], "reactions": [],
+                 "senderName": "Bob", "text": "", "timestamp": 1700000002000, "type": "media"},
+                {"isUnsent": false, "media": [], "reactions": [], "senderName": "Alice",
+                 "text": "hello", "timestamp": 1700000001000, "type": "text"}
+            ],
+            "participants": ["Alice", "Bob"],
+            "threadName": "Bob"
+        }"#;
+        let mut messages = Vec::new();
+        let header = stream_message_reader(
+            file.as_bytes(),
+            Path::new("e2ee_cutover/bob_1/message_1.json"),
+            |message| {
+                messages.push(message);
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(header.title, "Bob");
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].timestamp_ms, 1700000002000);
+        assert_eq!(
+            messages[0].photos.as_ref().unwrap()[0].uri,
+            "e2ee_cutover/bob_1/media/1.jpg"
+        );
+        assert_eq!(messages[1].content.as_deref(), Some("hello"));
+    }
+}
@@ -0,0 +1,149 @@
+//!
+//! Finding message threads, and which part of Messenger they're filed under
+//!
+use std::collections::BTreeSet;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+
+use enum_iterator::Sequence;
+use rayon::prelude::*;
+
+use super::stream::read_message_header;
+use super::{find_message_files, is_message_file};
+use crate::activity::ActivityTypes;
+use crate::source::Export;
+use crate::Skippable;
+
+/// The folders Messenger sorts threads into
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Sequence, clap::ValueEnum)]
+pub enum ThreadCategory {
+    Inbox,
+    ArchivedThreads,
+    FilteredThreads,
+    MessageRequests,
+    E2eeCutover,
+}
+
+impl ThreadCategory {
+    pub fn path(&self) -> &str {
+        match self {
+            ThreadCategory::Inbox => "inbox",
+            ThreadCategory::ArchivedThreads => "archived_threads",
+            ThreadCategory::FilteredThreads => "filtered_threads",
+            ThreadCategory::MessageRequests => "message_requests",
+            ThreadCategory::E2eeCutover => "e2ee_cutover",
+        }
+    }
+
+    /// Works out the category from a thread folder, eg `.../messages/inbox/bob_123`
+    pub fn from_thread_folder(folder: &Path) -> Option<Self> {
+        let relative = folder.strip_prefix(ActivityTypes::Messages.path()).ok()?;
+        let category = relative.components().next()?.as_os_str().to_str()?;
+        enum_iterator::all::<ThreadCategory>().find(|c| c.path() == category)
+    }
+}
+
+impl Display for ThreadCategory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.path())
+    }
+}
+
+/// A thread's folder and what's known about it from the first message file
+#[derive(Debug, Clone)]
+pub struct ThreadInfo {
+    pub folder: PathBuf,
+    pub category: Option<ThreadCategory>,
+    pub title: String,
+    pub participants: Vec<String>,
+    pub is_group: bool,
+}
+
+impl Display for ThreadInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let category = match self.category {
+            Some(category) => category.to_string(),
+            None => "unknown".to_string(),
+        };
+        write!(
+            f,
+            "{} ({}) [{}]",
+            self.title,
+            self.participants.join(", "),
+            category
+        )
+    }
+}
+
+/// Finds every thread in the export, limited to the given categories if there are any.
+///
+/// The thread's title and participants come from its first message file, without parsing the
+/// messages themselves.
+pub fn discover_threads(export: &Export, categories: &[ThreadCategory]) -> Vec<ThreadInfo> {
+    let mut folders: BTreeSet<PathBuf> = BTreeSet::new();
+    for path in export.files(&ActivityTypes::Messages.path()) {
+        if !is_message_file(&path) {
+            continue;
+        }
+        let folder = path.parent().unwrap().to_path_buf();
+        let category = ThreadCategory::from_thread_folder(&folder);
+        if !categories.is_empty() && !category.is_some_and(|c| categories.contains(&c)) {
+            continue;
+        }
+        folders.insert(folder);
+    }
+
+    folders
+        .into_par_iter()
+        .map(|folder| {
+            let category = ThreadCategory::from_thread_folder(&folder);
+            let first_file = find_message_files(export, &folder).into_iter().next();
+            let header = first_file.and_then(|file| match read_message_header(export, &file) {
+                Ok(header) => Some(header),
+                Err(err) => {
+                    eprintln!("Failed to read {}: {:?}", file.display(), err);
+                    None
+                }
+            });
+            match header {
+                Some(header) => ThreadInfo {
+                    folder,
+                    category,
+                    is_group: header.is_group(),
+                    title: header.title,
+                    participants: header.participants.into_iter().map(|p| p.name).collect(),
+                },
+                None => ThreadInfo {
+                    title: folder.display().to_string(),
+                    folder,
+                    category,
+                    participants: Vec::new(),
+                    is_group: false,
+                },
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::ThreadCategory;
+
+    #[test]
+    fn test_thread_category() {
+        assert_eq!(
+            ThreadCategory::from_thread_folder(Path::new(
+                "your_activity_across_facebook/messages/archived_threads/bob_123"
+            )),
+            Some(ThreadCategory::ArchivedThreads)
+        );
+        assert_eq!(
+            ThreadCategory::from_thread_folder(Path::new(
+                "your_activity_across_facebook/messages/stickers_used"
+            )),
+            None
+        );
+    }
+}
@@ -0,0 +1,325 @@
+//!
+//! The history of a group chat: who was added, who left, and what it was called when
+//!
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use super::kind::{MessageKind, SystemEvent};
+use super::stream::{read_message_header, MessageStream};
+use super::threads::discover_threads;
+use super::{find_message_files, target_folder};
+use crate::source::Export;
+use crate::{ActivityMessages, MagicError, OutputFormat};
+
+#[derive(Serialize, Debug, Clone)]
+pub struct GroupEvent {
+    pub timestamp_ms: u64,
+    pub sender: String,
+    #[serde(flatten)]
+    pub event: SystemEvent,
+}
+
+/// When someone was in the group. `None` means before or after what the export covers.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Membership {
+    pub name: String,
+    pub joined_ms: Option<u64>,
+    pub left_ms: Option<u64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct GroupTimeline {
+    pub thread: PathBuf,
+    pub title: String,
+    pub participants: Vec<String>,
+    pub is_still_participant: bool,
+    pub joinable_link: Option<String>,
+    pub image: Option<String>,
+    pub events: Vec<GroupEvent>,
+    pub members: Vec<Membership>,
+}
+
+/// Ends someone's current stint, or starts one that began before the export if they don't have one
+fn leave(
+    members: &mut Vec<Membership>,
+    open: &mut HashMap<String, usize>,
+    name: &str,
+    timestamp_ms: u64,
+) {
+    match open.remove(name) {
+        Some(index) => members[index].left_ms = Some(timestamp_ms),
+        None => members.push(Membership {
+            name: name.to_string(),
+            joined_ms: None,
+            left_ms: Some(timestamp_ms),
+        }),
+    }
+}
+
+/// Works out membership from the events, which need to be oldest first. Anyone in
+/// `participants` who never shows up in an event was there the whole time.
+pub fn memberships(events: &[GroupEvent], participants: &[String]) -> Vec<Membership> {
+    let mut members: Vec<Membership> = Vec::new();
+    // index into members of each person's current stint
+    let mut open: HashMap<String, usize> = HashMap::new();
+    for event in events {
+        match &event.event {
+            SystemEvent::ParticipantsAdded { added, .. } => {
+                for name in added {
+                    if !open.contains_key(name) {
+                        open.insert(name.clone(), members.len());
+                        members.push(Membership {
+                            name: name.clone(),
+                            joined_ms: Some(event.timestamp_ms),
+                            left_ms: None,
+                        });
+                    }
+                }
+            }
+            SystemEvent::ParticipantRemoved { removed, .. } => {
+                leave(&mut members, &mut open, removed, event.timestamp_ms)
+            }
+            SystemEvent::ParticipantLeft { actor } => {
+                leave(&mut members, &mut open, actor, event.timestamp_ms)
+            }
+            _ => {}
+        }
+    }
+
+    for name in participants {
+        if !members.iter().any(|m| &m.name == name) {
+            members.push(Membership {
+                name: name.clone(),
+                joined_ms: None,
+                left_ms: None,
+            });
+        }
+    }
+    members
+}
+
+/// Builds the timeline for one thread folder
+pub fn group_timeline(export: &Export, folder: &Path) -> Result<GroupTimeline, MagicError> {
+    let first_file = find_message_files(export, folder)
+        .into_iter()
+        .next()
+        .ok_or_else(|| MagicError::Generic(format!("No message files in {}", folder.display())))?;
+    let header = read_message_header(export, &first_file)?;
+
+    let mut events = Vec::new();
+    for message in MessageStream::new(export, folder) {
+        let message = message?;
+        if let MessageKind::System(event) = message.kind() {
+            events.push(GroupEvent {
+                timestamp_ms: message.timestamp_ms,
+                sender: message.sender_name,
+                event,
+            });
+        }
+    }
+    events.sort_by_key(|event| event.timestamp_ms);
+
+    let participants: Vec<String> = header.participants.into_iter().map(|p| p.name).collect();
+    Ok(GroupTimeline {
+        thread: folder.to_path_buf(),
+        members: memberships(&events, &participants),
+        title: header.title,
+        participants,
+        is_still_participant: header.is_still_participant,
+        joinable_link: header.joinable_mode.map(|mode| mode.link),
+        image: header.image.map(|image| image.uri),
+        events,
+    })
+}
+
+/// A row of the CSV, either an event or someone's time in the group. `member` rows use `actor`
+/// for who it is and `joined`/`left` for when, blank if it's outside what the export covers.
+#[derive(Serialize)]
+struct GroupRow<'a> {
+    thread: String,
+    title: &'a str,
+    row: &'a str,
+    timestamp: String,
+    sender: &'a str,
+    event: &'a str,
+    actor: &'a str,
+    target: String,
+    value: &'a str,
+    joined: String,
+    left: String,
+}
+
+fn format_ms(timestamp_ms: Option<u64>) -> String {
+    timestamp_ms
+        .and_then(|ms| DateTime::<Utc>::from_timestamp_millis(ms as i64))
+        .map(|ts| ts.to_rfc3339())
+        .unwrap_or_default()
+}
+
+fn write_csv(timelines: &[GroupTimeline], writer: impl Write) -> Result<(), MagicError> {
+    let mut writer = csv::Writer::from_writer(writer);
+    for timeline in timelines {
+        let thread = timeline.thread.display().to_string();
+        for event in timeline.events.iter() {
+            writer
+                .serialize(GroupRow {
+                    thread: thread.clone(),
+                    title: &timeline.title,
+                    row: "event",
+                    timestamp: format_ms(Some(event.timestamp_ms)),
+                    sender: &event.sender,
+                    event: event.event.name(),
+                    actor: event.event.actor(),
+                    target: event.event.targets().join("; "),
+                    value: event.event.value().unwrap_or_default(),
+                    joined: String::new(),
+                    left: String::new(),
+                })
+                .map_err(|err| MagicError::Generic(err.to_string()))?;
+        }
+        for member in timeline.members.iter() {
+            writer
+                .serialize(GroupRow {
+                    thread: thread.clone(),
+                    title: &timeline.title,
+                    row: "member",
+                    timestamp: String::new(),
+                    sender: "",
+                    event: "",
+                    actor: &member.name,
+                    target: String::new(),
+                    value: "",
+                    joined: format_ms(member.joined_ms),
+                    left: format_ms(member.left_ms),
+                })
+                .map_err(|err| MagicError::Generic(err.to_string()))?;
+        }
+    }
+    writer
+        .flush()
+        .map_err(|err| MagicError::Generic(err.to_string()))
+}
+
+/// Timelines for the thread given on the command line, or every group chat if there isn't one
+pub fn group_timelines(
+    export: &Export,
+    msg: ActivityMessages,
+    format: OutputFormat,
+    output: Option<PathBuf>,
+) -> Result<(), MagicError> {
+    let folders: Vec<PathBuf> = match msg.target_folder {
+        Some(_) => vec![target_folder(export, &msg)],
+        None => discover_threads(export, &msg.category)
+            .into_iter()
+            .filter(|thread| thread.is_group)
+            .map(|thread| thread.folder)
+            .collect(),
+    };
+
+    let mut timelines = Vec::new();
+    for folder in folders {
+        match group_timeline(export, &folder) {
+            Ok(timeline) => timelines.push(timeline),
+            Err(err) => eprintln!("Failed to read {}: {:?}", folder.display(), err),
+        }
+    }
+
+    let writer: Box<dyn Write> = match &output {
+        Some(path) => {
+            Box::new(File::create(path).map_err(|err| MagicError::Generic(err.to_string()))?)
+        }
+        None => Box::new(std::io::stdout()),
+    };
+    match format {
+        OutputFormat::Json => serde_json::to_writer_pretty(writer, &timelines)
+            .map_err(|err| MagicError::Generic(err.to_string())),
+        OutputFormat::Csv => write_csv(&timelines, writer),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{memberships, write_csv, GroupEvent, GroupTimeline, Membership};
+    use crate::activity::messages::kind::SystemEvent;
+    use crate::activity::messages::MessageFileParser;
+
+    fn event(timestamp_ms: u64, content: &str) -> GroupEvent {
+        GroupEvent {
+            timestamp_ms,
+            sender: "Alice".to_string(),
+            event: SystemEvent::parse(content).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_memberships() {
+        let events = vec![
+            event(1, "Alice added Bob and Carol to the group."),
+            event(2, "Bob left the group."),
+            event(3, "Alice removed Dave from the group."),
+        ];
+        let members = memberships(&events, &["Alice".to_string(), "Carol".to_string()]);
+        let member = |name: &str, joined_ms, left_ms| Membership {
+            name: name.to_string(),
+            joined_ms,
+            left_ms,
+        };
+        assert_eq!(
+            members,
+            vec![
+                member("Bob", Some(1), Some(2)),
+                member("Carol", Some(1), None),
+                member("Dave", None, Some(3)),
+                member("Alice", None, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_groups() {
+        let header = |extra: &str| -> MessageFileParser {
+            serde_json::from_str(&format!(
+                r#"{{"participants": [{{"name": "Alice"}}, {{"name": "Bob"}}, {{"name": "Carol"}}],
+                "messages": [], "title": "Chat", "is_still_participant": true,
+                "thread_path": "inbox/chat_1", "magic_words": []{}}}"#,
+                extra
+            ))
+            .unwrap()
+        };
+        // a stale third participant doesn't make it a group
+        assert!(!header("").is_group());
+        assert!(header(r#", "joinable_mode": {"mode": 1, "link": ""}"#).is_group());
+        assert!(header(r#", "thread_type": "RegularGroup""#).is_group());
+        assert!(!header(r#", "thread_type": "Regular""#).is_group());
+
+        let events = vec![event(1, "Alice added Bob to the group.")];
+        let timeline = GroupTimeline {
+            thread: PathBuf::from("inbox/chat_1"),
+            title: "Chat".to_string(),
+            participants: vec!["Alice".to_string()],
+            is_still_participant: true,
+            joinable_link: None,
+            image: None,
+            members: memberships(&events, &["Alice".to_string()]),
+            events,
+        };
+        let mut csv = Vec::new();
+        write_csv(&[timeline], &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let rows: Vec<&str> = csv.lines().collect();
+        assert_eq!(rows.len(), 4);
+        assert!(rows[1].contains(",event,"));
+        assert!(
+            rows[2].contains(",member,")
+                && rows[2].contains("Bob,,,1970-01-01T00:00:00.001+00:00,")
+        );
+        assert!(rows[3].ends_with(",member,,,,Alice,,,,"));
+    }
+}
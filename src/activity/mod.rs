@@ -1,21 +1,26 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use enum_iterator::Sequence;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
 
-use crate::{Skippable, BASE_PATH};
+use crate::source::Export;
+use crate::Skippable;
 
+pub mod albums;
+pub mod bug_bounty;
 pub mod messages;
+pub mod posts;
 
 /// Activity parser
-///
-///
-
 static PARENT_FOLDER: &str = "your_activity_across_facebook";
 
 #[derive(Debug, PartialEq, Sequence)]
 pub enum ActivityTypes {
+    Albums,
     BugBounty,
     Messages,
+    Posts,
 }
 
 // impl ActivityTypes {}
@@ -23,16 +28,50 @@ pub enum ActivityTypes {
 impl Skippable for ActivityTypes {
     fn path(&self) -> PathBuf {
         match self {
-            ActivityTypes::BugBounty => {
-                PathBuf::from(&format!("{}/{}/bug_bounty", BASE_PATH, PARENT_FOLDER))
-            }
-            ActivityTypes::Messages => {
-                PathBuf::from(&format!("{}/{}/messages", BASE_PATH, PARENT_FOLDER))
-            }
+            ActivityTypes::Albums => PathBuf::from(PARENT_FOLDER).join("posts").join("album"),
+            ActivityTypes::BugBounty => PathBuf::from(PARENT_FOLDER).join("bug_bounty"),
+            ActivityTypes::Messages => PathBuf::from(PARENT_FOLDER).join("messages"),
+            ActivityTypes::Posts => PathBuf::from(PARENT_FOLDER).join("posts"),
         }
     }
 
-    fn skippable(&self) -> bool {
-        self.path().join("no-data.txt").exists()
+    fn skippable(&self, export: &Export) -> bool {
+        export.exists(&self.path().join("no-data.txt"))
+    }
+}
+
+/// What to do with a record that doesn't match the types
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ParseMode {
+    /// Fail, the same as messages do
+    #[default]
+    Strict,
+    /// Leave it out and carry on, saying what was skipped
+    Lenient,
+}
+
+/// From the `--lenient` flag
+impl From<bool> for ParseMode {
+    fn from(lenient: bool) -> Self {
+        match lenient {
+            true => ParseMode::Lenient,
+            false => ParseMode::Strict,
+        }
     }
 }
+
+/// Parses each value on its own, so one bad record only loses that record. `path` is where
+/// they're from, for the warnings.
+pub fn parse_lenient<T: DeserializeOwned>(values: Vec<Value>, path: &Path) -> Vec<T> {
+    values
+        .into_iter()
+        .enumerate()
+        .filter_map(|(index, value)| match serde_json::from_value(value) {
+            Ok(record) => Some(record),
+            Err(err) => {
+                eprintln!("{}: skipping record {}: {}", path.display(), index, err);
+                None
+            }
+        })
+        .collect()
+}
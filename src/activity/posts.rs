@@ -0,0 +1,496 @@
+//!
+//! Posts, your own, ones on other people's timelines and check-ins
+//!
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::{parse_lenient, ActivityTypes, ParseMode};
+use crate::datasource::{DataSource, TimelineEvent};
+use crate::source::Export;
+use crate::{MagicError, Skippable};
+
+pub mod site;
+
+/// Posts files are a list, except some older ones which put the list under a key
+#[derive(Deserialize, JsonSchema, Debug)]
+#[serde(untagged)]
+pub enum PostsFile {
+    List(Vec<Post>),
+    Keyed(BTreeMap<String, Vec<Post>>),
+}
+
+impl PostsFile {
+    pub fn into_posts(self) -> Vec<Post> {
+        match self {
+            PostsFile::List(posts) => posts,
+            PostsFile::Keyed(lists) => lists.into_values().flatten().collect(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PostKind {
+    #[default]
+    Own,
+    /// Written on someone else's timeline
+    OtherTimeline,
+    CheckIn,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Post {
+    /// Worked out from the file and attachments when it's loaded, so it's written out but isn't
+    /// in Facebook's files
+    #[serde(skip_deserializing)]
+    #[schemars(skip)]
+    pub kind: PostKind,
+    #[schemars(with = "i64")]
+    #[serde(with = "chrono::serde::ts_seconds")]
+    pub timestamp: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<PostAttachment>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub data: Vec<PostData>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<PostTag>,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct PostAttachment {
+    pub data: Vec<AttachmentData>,
+}
+
+/// One thing attached to a post, only one of the fields is ever set
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct AttachmentData {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub media: Option<PostMedia>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub place: Option<PostPlace>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub external_context: Option<ExternalContext>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct PostMedia {
+    pub uri: String,
+    #[schemars(with = "Option<i64>")]
+    #[serde(
+        with = "chrono::serde::ts_seconds_option",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub creation_timestamp: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub media_metadata: Option<MediaMetadata>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct MediaMetadata {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub photo_metadata: Option<ExifMetadata>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub video_metadata: Option<ExifMetadata>,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ExifMetadata {
+    #[serde(default)]
+    pub exif_data: Vec<ExifData>,
+}
+
+/// What Facebook kept of the EXIF data, everything's optional and it's often empty
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ExifData {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upload_ip: Option<String>,
+    #[schemars(with = "Option<i64>")]
+    #[serde(
+        with = "chrono::serde::ts_seconds_option",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub upload_timestamp: Option<DateTime<Utc>>,
+    #[schemars(with = "Option<i64>")]
+    #[serde(
+        with = "chrono::serde::ts_seconds_option",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub taken_timestamp: Option<DateTime<Utc>>,
+    #[schemars(with = "Option<i64>")]
+    #[serde(
+        with = "chrono::serde::ts_seconds_option",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub modified_timestamp: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub camera_make: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub camera_model: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exposure: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub focal_length: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub f_stop: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iso_speed: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latitude: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub longitude: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub orientation: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub original_width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub original_height: Option<u32>,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct PostPlace {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub coordinate: Option<PlaceCoordinate>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct PlaceCoordinate {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ExternalContext {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+}
+
+/// The `data` list, which mixes the post's text with when it was changed
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct PostData {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub post: Option<String>,
+    #[schemars(with = "Option<i64>")]
+    #[serde(
+        with = "chrono::serde::ts_seconds_option",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub update_timestamp: Option<DateTime<Utc>>,
+    #[schemars(with = "Option<i64>")]
+    #[serde(
+        with = "chrono::serde::ts_seconds_option",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub backdated_timestamp: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct PostTag {
+    pub name: String,
+}
+
+impl Post {
+    /// What was written, if anything
+    pub fn text(&self) -> Option<&str> {
+        self.data.iter().find_map(|data| data.post.as_deref())
+    }
+
+    fn attached(&self) -> impl Iterator<Item = &AttachmentData> {
+        self.attachments
+            .iter()
+            .flat_map(|attachment| attachment.data.iter())
+    }
+
+    pub fn media(&self) -> Vec<&PostMedia> {
+        self.attached()
+            .filter_map(|data| data.media.as_ref())
+            .collect()
+    }
+
+    pub fn places(&self) -> Vec<&PostPlace> {
+        self.attached()
+            .filter_map(|data| data.place.as_ref())
+            .collect()
+    }
+
+    pub fn links(&self) -> Vec<&str> {
+        self.attached()
+            .filter_map(|data| data.external_context.as_ref()?.url.as_deref())
+            .collect()
+    }
+
+    /// The title if there is one, otherwise the start of the text
+    pub fn headline(&self) -> String {
+        match (self.title.as_deref(), self.text()) {
+            (Some(title), _) if !title.is_empty() => title.to_string(),
+            (_, Some(text)) => {
+                let line = text.lines().next().unwrap_or_default();
+                match line.char_indices().nth(60) {
+                    Some((end, _)) => format!("{}...", &line[..end]),
+                    None => line.to_string(),
+                }
+            }
+            _ => "Post".to_string(),
+        }
+    }
+}
+
+/// Which kind of post a file's posts are, check-ins are picked out by their place
+fn post_kind(path: &Path, post: &Post) -> PostKind {
+    let filename = path
+        .file_name()
+        .and_then(|filename| filename.to_str())
+        .unwrap_or_default();
+    if filename.contains("other_people") {
+        PostKind::OtherTimeline
+    } else if !post.places().is_empty() {
+        PostKind::CheckIn
+    } else {
+        PostKind::Own
+    }
+}
+
+pub fn parse_posts_file(
+    export: &Export,
+    path: &Path,
+    mode: ParseMode,
+) -> Result<Vec<Post>, MagicError> {
+    let value: Value = serde_json::from_reader(export.open(path)?)
+        .map_err(|err| MagicError::Generic(format!("{}: {}", path.display(), err)))?;
+    let mut posts = match mode {
+        ParseMode::Strict => serde_json::from_value::<PostsFile>(value)
+            .map(PostsFile::into_posts)
+            .map_err(|err| {
+                MagicError::Generic(format!("{}: not a posts file: {}", path.display(), err))
+            })?,
+        ParseMode::Lenient => {
+            let values = match value {
+                Value::Array(values) => values,
+                Value::Object(lists) => lists
+                    .into_iter()
+                    .filter_map(|(_, list)| match list {
+                        Value::Array(values) => Some(values),
+                        _ => None,
+                    })
+                    .flatten()
+                    .collect(),
+                _ => Vec::new(),
+            };
+            parse_lenient(values, path)
+        }
+    };
+    for post in posts.iter_mut() {
+        post.kind = post_kind(path, post);
+    }
+    Ok(posts)
+}
+
+/// Every post in the export, oldest first
+pub fn find_posts(export: &Export, mode: ParseMode) -> Result<Vec<Post>, MagicError> {
+    let mut posts = Vec::new();
+    for path in DataSource::discover(&PostsSource, export) {
+        posts.extend(parse_posts_file(export, &path, mode)?);
+    }
+    posts.sort_by_key(|post| post.timestamp);
+    Ok(posts)
+}
+
+/// Posts, for the [registry](crate::datasource::registry)
+pub struct PostsSource;
+
+impl Skippable for PostsSource {
+    fn path(&self) -> PathBuf {
+        ActivityTypes::Posts.path()
+    }
+
+    fn skippable(&self, export: &Export) -> bool {
+        ActivityTypes::Posts.skippable(export)
+    }
+}
+
+impl DataSource for PostsSource {
+    type Record = Post;
+
+    fn name(&self) -> &'static str {
+        "posts"
+    }
+
+    /// `your_posts__check_ins__photos_and_videos_1.json`, `your_posts_on_other_peoples_timelines`
+    /// and the like, the rest of the folder is albums and edits
+    fn handles(&self, path: &Path) -> bool {
+        let posts = ActivityTypes::Posts.path();
+        path.parent() == Some(posts.as_path())
+            && path.extension().is_some_and(|ext| ext == "json")
+            && path
+                .file_name()
+                .and_then(|filename| filename.to_str())
+                .is_some_and(|filename| {
+                    filename.starts_with("your_posts") || filename.contains("check_ins")
+                })
+    }
+
+    fn parse(&self, export: &Export, path: &Path) -> Result<Vec<Post>, MagicError> {
+        parse_posts_file(export, path, ParseMode::Lenient)
+    }
+
+    fn event(&self, _path: &Path, post: &Post) -> Option<TimelineEvent> {
+        Some(TimelineEvent {
+            timestamp: post.timestamp,
+            source: "posts",
+            actor: None,
+            summary: post.headline(),
+        })
+    }
+
+    fn stats(&self, posts: &[Post]) -> BTreeMap<String, usize> {
+        let mut stats = BTreeMap::from([
+            ("posts".to_string(), posts.len()),
+            (
+                "media".to_string(),
+                posts.iter().map(|post| post.media().len()).sum(),
+            ),
+        ]);
+        for post in posts {
+            let kind = match post.kind {
+                PostKind::Own => "own",
+                PostKind::OtherTimeline => "on other timelines",
+                PostKind::CheckIn => "check-ins",
+            };
+            *stats.entry(kind.to_string()).or_default() += 1;
+        }
+        stats
+    }
+}
+
+/// Lists the posts, oldest first
+pub fn list_posts(export: &Export, mode: ParseMode) -> Result<(), MagicError> {
+    let posts = find_posts(export, mode)?;
+    for post in posts.iter() {
+        println!(
+            "{} {:?}: {} ({} media)",
+            post.timestamp.format("%Y-%m-%d %H:%M"),
+            post.kind,
+            post.headline(),
+            post.media().len()
+        );
+    }
+    println!("Total: {} posts", posts.len());
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::path::Path;
+
+    use tempfile::TempDir;
+
+    use super::{parse_posts_file, PostKind};
+    use crate::activity::{ActivityTypes, ParseMode};
+    use crate::source::{DirectorySource, Export};
+    use crate::test_util::{temp_dir, write_file};
+    use crate::Skippable;
+
+    pub(crate) static TEST_POSTS: &str = r#"[
+        {
+            "timestamp": 1600000000,
+            "attachments": [{"data": [{"media": {
+                "uri": "your_activity_across_facebook/posts/media/album_1/1.jpg",
+                "creation_timestamp": 1600000000,
+                "media_metadata": {"photo_metadata": {"exif_data": [{"taken_timestamp": 1590000000, "upload_ip": "192.0.2.1"}]}},
+                "title": "",
+                "description": "A view"
+            }}]}],
+            "data": [{"post": "Look at this"}, {"update_timestamp": 1600000100}],
+            "title": "Test User updated their status.",
+            "tags": [{"name": "Alice"}]
+        },
+        {
+            "timestamp": 1600500000,
+            "attachments": [{"data": [{"place": {"name": "The Pub", "coordinate": {"latitude": 51.5, "longitude": -0.1}}}]}],
+            "title": "Test User checked in to The Pub."
+        }
+    ]"#;
+
+    /// Writes [TEST_POSTS] into a temporary export
+    pub(crate) fn posts_export() -> TempDir {
+        let temp = temp_dir();
+        let folder = ActivityTypes::Posts.path();
+        write_file(
+            temp.path(),
+            folder.join("your_posts__check_ins__photos_and_videos_1.json"),
+            TEST_POSTS,
+        );
+        write_file(temp.path(), folder.join("media/album_1/1.jpg"), b"jpeg");
+        temp
+    }
+
+    #[test]
+    fn test_parse_posts() {
+        let root = posts_export();
+        let export = Export::from_source(DirectorySource::new(root.path()));
+        let path = ActivityTypes::Posts
+            .path()
+            .join("your_posts__check_ins__photos_and_videos_1.json");
+
+        let posts = parse_posts_file(&export, &path, ParseMode::Strict).unwrap();
+        assert_eq!(posts.len(), 2);
+        assert_eq!(posts[0].kind, PostKind::Own);
+        assert_eq!(posts[0].text(), Some("Look at this"));
+        assert_eq!(posts[0].tags[0].name, "Alice");
+        let media = posts[0].media();
+        let exif = &media[0].media_metadata.as_ref().unwrap().photo_metadata;
+        assert_eq!(
+            exif.as_ref().unwrap().exif_data[0]
+                .taken_timestamp
+                .unwrap()
+                .timestamp(),
+            1590000000
+        );
+        assert_eq!(posts[1].kind, PostKind::CheckIn);
+        assert_eq!(posts[1].places()[0].name, "The Pub");
+        let written = serde_json::to_value(&posts[1]).unwrap();
+        assert_eq!(written["kind"], "check_in");
+
+        let other = Path::new("posts/your_posts_on_other_peoples_timelines_1.json");
+        assert_eq!(super::post_kind(other, &posts[0]), PostKind::OtherTimeline);
+    }
+}
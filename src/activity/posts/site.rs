@@ -0,0 +1,463 @@
+//!
+//! Renders posts as a static site, one page per post with the media copied alongside
+//!
+//! ```text
+//! index.html          every month with posts, by year
+//! 2020/09/index.html  the month's posts
+//! 2020/09/2020-09-13-122640.html
+//! media/...
+//! ```
+//!
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use chrono::Datelike;
+
+use super::{find_posts, Post, PostKind};
+use crate::activity::messages::links::html_escape;
+use crate::activity::ParseMode;
+use crate::source::Export;
+use crate::{MagicError, SiteFormat};
+
+impl SiteFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            SiteFormat::Markdown => "md",
+            SiteFormat::Html => "html",
+        }
+    }
+}
+
+/// A page's content, before it's rendered in one format or the other
+enum Block {
+    Heading(String),
+    Text(String),
+    /// Bullet list of (text, href)
+    Links(Vec<(String, String)>),
+    /// Links on one line, for getting around
+    Nav(Vec<(String, String)>),
+    Image {
+        src: String,
+        alt: String,
+    },
+    Video {
+        src: String,
+    },
+}
+
+fn render(format: SiteFormat, title: &str, blocks: &[Block]) -> String {
+    match format {
+        SiteFormat::Markdown => render_markdown(blocks),
+        SiteFormat::Html => render_html(title, blocks),
+    }
+}
+
+/// Markdown doesn't care about most characters, but these would start links or formatting
+fn markdown_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '[' | ']' | '*' | '_' | '`' | '#' | '<' | '>') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn render_markdown(blocks: &[Block]) -> String {
+    let link = |text: &str, href: &str| format!("[{}](<{}>)", markdown_escape(text), href);
+    let mut page = String::new();
+    for block in blocks {
+        match block {
+            Block::Heading(text) => page.push_str(&format!("# {}\n", markdown_escape(text))),
+            Block::Text(text) => {
+                // two spaces keeps line breaks within the paragraph
+                let lines: Vec<String> = text.lines().map(markdown_escape).collect();
+                page.push_str(&format!("{}\n", lines.join("  \n")));
+            }
+            Block::Links(links) => {
+                for (text, href) in links {
+                    page.push_str(&format!("- {}\n", link(text, href)));
+                }
+            }
+            Block::Nav(links) => {
+                let links: Vec<String> =
+                    links.iter().map(|(text, href)| link(text, href)).collect();
+                page.push_str(&format!("{}\n", links.join(" | ")));
+            }
+            Block::Image { src, alt } => {
+                page.push_str(&format!("![{}](<{}>)\n", markdown_escape(alt), src))
+            }
+            Block::Video { src } => page.push_str(&format!("{}\n", link("Video", src))),
+        }
+        page.push('\n');
+    }
+    page
+}
+
+fn render_html(title: &str, blocks: &[Block]) -> String {
+    let link = |text: &str, href: &str| {
+        format!(
+            r#"<a href="{}">{}</a>"#,
+            html_escape(href),
+            html_escape(text)
+        )
+    };
+    let mut page = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n</head>\n<body>\n",
+        html_escape(title)
+    );
+    for block in blocks {
+        match block {
+            Block::Heading(text) => page.push_str(&format!("<h1>{}</h1>\n", html_escape(text))),
+            Block::Text(text) => {
+                let lines: Vec<String> = text.lines().map(html_escape).collect();
+                page.push_str(&format!("<p>{}</p>\n", lines.join("<br>\n")));
+            }
+            Block::Links(links) => {
+                page.push_str("<ul>\n");
+                for (text, href) in links {
+                    page.push_str(&format!("<li>{}</li>\n", link(text, href)));
+                }
+                page.push_str("</ul>\n");
+            }
+            Block::Nav(links) => {
+                let links: Vec<String> =
+                    links.iter().map(|(text, href)| link(text, href)).collect();
+                page.push_str(&format!("<nav>{}</nav>\n", links.join(" | ")));
+            }
+            Block::Image { src, alt } => page.push_str(&format!(
+                "<p><img src=\"{}\" alt=\"{}\" style=\"max-width: 100%\"></p>\n",
+                html_escape(src),
+                html_escape(alt)
+            )),
+            Block::Video { src } => page.push_str(&format!(
+                "<p><video src=\"{}\" controls style=\"max-width: 100%\"></video></p>\n",
+                html_escape(src)
+            )),
+        }
+    }
+    page.push_str("</body>\n</html>\n");
+    page
+}
+
+fn write(path: &Path, contents: &str) -> Result<(), MagicError> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|err| MagicError::Generic(format!("{}: {}", parent.display(), err)))?;
+    }
+    std::fs::write(path, contents)
+        .map_err(|err| MagicError::Generic(format!("{}: {}", path.display(), err)))
+}
+
+/// A post and where its page goes
+struct Page<'a> {
+    post: &'a Post,
+    /// `2020-09-13-122640`, with `-2` and so on added if there's more than one in a second
+    slug: String,
+}
+
+impl Page<'_> {
+    fn month(&self) -> (i32, u32) {
+        (self.post.timestamp.year(), self.post.timestamp.month())
+    }
+
+    /// Where the page is relative to the root of the site
+    fn path(&self, format: SiteFormat) -> String {
+        let (year, month) = self.month();
+        format!("{}/{:02}/{}.{}", year, month, self.slug, format.extension())
+    }
+}
+
+fn month_name(year: i32, month: u32) -> String {
+    chrono::NaiveDate::from_ymd_opt(year, month, 1)
+        .map(|date| date.format("%B %Y").to_string())
+        .unwrap_or_else(|| format!("{}-{:02}", year, month))
+}
+
+fn month_path(year: i32, month: u32, format: SiteFormat) -> String {
+    format!("{}/{:02}/index.{}", year, month, format.extension())
+}
+
+/// Everything below the root is two folders deep, this gets back up from there
+static UP: &str = "../../";
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct SiteSummary {
+    pub posts: usize,
+    pub months: usize,
+    pub media: usize,
+}
+
+/// Writes the site for `posts`, which should be oldest first
+pub fn write_site(
+    export: &Export,
+    posts: &[Post],
+    output: &Path,
+    format: SiteFormat,
+) -> Result<SiteSummary, MagicError> {
+    let ext = format.extension();
+    let mut seen: BTreeMap<String, usize> = BTreeMap::new();
+    let pages: Vec<Page> = posts
+        .iter()
+        .map(|post| {
+            let base = post.timestamp.format("%Y-%m-%d-%H%M%S").to_string();
+            let count = seen.entry(base.clone()).or_default();
+            *count += 1;
+            let slug = match count {
+                1 => base,
+                n => format!("{}-{}", base, n),
+            };
+            Page { post, slug }
+        })
+        .collect();
+
+    let mut months: BTreeMap<(i32, u32), Vec<&Page>> = BTreeMap::new();
+    for page in pages.iter() {
+        months.entry(page.month()).or_default().push(page);
+    }
+    let month_keys: Vec<(i32, u32)> = months.keys().copied().collect();
+
+    let mut summary = SiteSummary {
+        posts: pages.len(),
+        months: months.len(),
+        media: 0,
+    };
+
+    for (index, page) in pages.iter().enumerate() {
+        let post = page.post;
+        let (year, month) = page.month();
+        let mut nav = vec![
+            ("Home".to_string(), format!("{}index.{}", UP, ext)),
+            (month_name(year, month), format!("index.{}", ext)),
+        ];
+        if let Some(previous) = index.checked_sub(1).map(|index| &pages[index]) {
+            nav.push((
+                "Previous".to_string(),
+                format!("{}{}", UP, previous.path(format)),
+            ));
+        }
+        if let Some(next) = pages.get(index + 1) {
+            nav.push(("Next".to_string(), format!("{}{}", UP, next.path(format))));
+        }
+
+        let mut blocks = vec![
+            Block::Nav(nav),
+            Block::Heading(post.headline()),
+            Block::Text(format!(
+                "{}{}",
+                post.timestamp.format("%A %-d %B %Y, %H:%M UTC"),
+                match post.kind {
+                    PostKind::Own => "",
+                    PostKind::OtherTimeline => ", on someone else's timeline",
+                    PostKind::CheckIn => ", check-in",
+                }
+            )),
+        ];
+        if let Some(text) = post.text() {
+            blocks.push(Block::Text(text.to_string()));
+        }
+        if !post.tags.is_empty() {
+            let names: Vec<&str> = post.tags.iter().map(|tag| tag.name.as_str()).collect();
+            blocks.push(Block::Text(format!("With {}", names.join(", "))));
+        }
+        for place in post.places() {
+            let mut text = format!("At {}", place.name);
+            if let Some(address) = place.address.as_deref().filter(|a| !a.is_empty()) {
+                text.push_str(&format!(", {}", address));
+            }
+            match &place.coordinate {
+                Some(coordinate) => blocks.push(Block::Links(vec![(
+                    text,
+                    format!(
+                        "https://www.openstreetmap.org/?mlat={}&mlon={}",
+                        coordinate.latitude, coordinate.longitude
+                    ),
+                )])),
+                None => blocks.push(Block::Text(text)),
+            }
+        }
+        let links: Vec<(String, String)> = post
+            .links()
+            .into_iter()
+            .map(|link| (link.to_string(), link.to_string()))
+            .collect();
+        if !links.is_empty() {
+            blocks.push(Block::Links(links));
+        }
+
+        for (number, media) in post.media().into_iter().enumerate() {
+            let uri = Path::new(&media.uri);
+            if !export.exists(uri) {
+                eprintln!("{} is missing, leaving it out", media.uri);
+                continue;
+            }
+            let filename = format!(
+                "{}_{}.{}",
+                page.slug,
+                number + 1,
+                uri.extension()
+                    .and_then(|ext| ext.to_str())
+                    .unwrap_or("bin")
+            );
+            let target = output.join("media").join(&filename);
+            if let Some(parent) = target.parent() {
+                std::fs::create_dir_all(parent)
+                    .map_err(|err| MagicError::Generic(format!("{}: {}", parent.display(), err)))?;
+            }
+            export.copy(uri, &target)?;
+            summary.media += 1;
+
+            let src = format!("{}media/{}", UP, filename);
+            let description = media
+                .description
+                .clone()
+                .or_else(|| media.title.clone())
+                .filter(|description| !description.is_empty());
+            match uri.extension().and_then(|ext| ext.to_str()) {
+                Some("mp4" | "mov" | "webm") => blocks.push(Block::Video { src }),
+                _ => blocks.push(Block::Image {
+                    src,
+                    alt: description.clone().unwrap_or_default(),
+                }),
+            }
+            if let Some(description) = description {
+                blocks.push(Block::Text(description));
+            }
+        }
+
+        let title = post.headline();
+        write(
+            &output.join(page.path(format)),
+            &render(format, &title, &blocks),
+        )?;
+    }
+
+    for (index, ((year, month), month_pages)) in months.iter().enumerate() {
+        let mut nav = vec![("Home".to_string(), format!("{}index.{}", UP, ext))];
+        if let Some((year, month)) = index.checked_sub(1).map(|index| month_keys[index]) {
+            nav.push((
+                month_name(year, month),
+                format!("{}{}", UP, month_path(year, month, format)),
+            ));
+        }
+        if let Some((year, month)) = month_keys.get(index + 1) {
+            nav.push((
+                month_name(*year, *month),
+                format!("{}{}", UP, month_path(*year, *month, format)),
+            ));
+        }
+        let title = month_name(*year, *month);
+        let links = month_pages
+            .iter()
+            .map(|page| {
+                (
+                    format!(
+                        "{} {}",
+                        page.post.timestamp.format("%-d %B"),
+                        page.post.headline()
+                    ),
+                    format!("{}.{}", page.slug, ext),
+                )
+            })
+            .collect();
+        let blocks = [
+            Block::Nav(nav),
+            Block::Heading(title.clone()),
+            Block::Links(links),
+        ];
+        write(
+            &output.join(month_path(*year, *month, format)),
+            &render(format, &title, &blocks),
+        )?;
+    }
+
+    let mut blocks = vec![Block::Heading("Timeline".to_string())];
+    if let (Some(first), Some(last)) = (posts.first(), posts.last()) {
+        blocks.push(Block::Text(format!(
+            "{} posts, {} to {}",
+            posts.len(),
+            first.timestamp.format("%-d %B %Y"),
+            last.timestamp.format("%-d %B %Y")
+        )));
+    }
+    let mut years: BTreeMap<i32, Vec<(String, String)>> = BTreeMap::new();
+    for ((year, month), month_pages) in months.iter() {
+        years.entry(*year).or_default().push((
+            format!(
+                "{} ({} posts)",
+                month_name(*year, *month),
+                month_pages.len()
+            ),
+            month_path(*year, *month, format),
+        ));
+    }
+    for (year, links) in years {
+        blocks.push(Block::Heading(year.to_string()));
+        blocks.push(Block::Links(links));
+    }
+    write(
+        &output.join(format!("index.{}", ext)),
+        &render(format, "Timeline", &blocks),
+    )?;
+
+    Ok(summary)
+}
+
+/// Writes every post in the export out as a site in `output`
+pub fn posts_site(
+    export: &Export,
+    mode: ParseMode,
+    output: PathBuf,
+    format: SiteFormat,
+) -> Result<(), MagicError> {
+    let posts = find_posts(export, mode)?;
+    let summary = write_site(export, &posts, &output, format)?;
+    println!(
+        "Wrote {} posts over {} months with {} media files to {}",
+        summary.posts,
+        summary.months,
+        summary.media,
+        output.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{write_site, SiteSummary};
+    use crate::activity::posts::find_posts;
+    use crate::activity::posts::tests::posts_export;
+    use crate::activity::ParseMode;
+    use crate::source::Export;
+    use crate::SiteFormat;
+
+    #[test]
+    fn test_write_site() {
+        let temp = posts_export();
+        let root = temp.path().to_path_buf();
+        let export = Export::load(std::slice::from_ref(&root)).unwrap();
+        let posts = find_posts(&export, ParseMode::Strict).unwrap();
+        let output = root.join("site");
+
+        let summary = write_site(&export, &posts, &output, SiteFormat::Html).unwrap();
+        assert_eq!(
+            summary,
+            SiteSummary {
+                posts: 2,
+                months: 1,
+                media: 1
+            }
+        );
+        let page = std::fs::read_to_string(output.join("2020/09/2020-09-13-122640.html")).unwrap();
+        assert!(page.contains("Look at this"));
+        assert!(page.contains(r#"<img src="../../media/2020-09-13-122640_1.jpg""#));
+        assert!(page.contains(r#"href="../../2020/09/2020-09-19-072000.html""#));
+        assert!(output.join("media/2020-09-13-122640_1.jpg").exists());
+        assert!(output.join("2020/09/index.html").exists());
+
+        write_site(&export, &posts, &output, SiteFormat::Markdown).unwrap();
+        let index = std::fs::read_to_string(output.join("index.md")).unwrap();
+        assert!(index.contains("[September 2020 (2 posts)](<2020/09/index.md>)"));
+    }
+}
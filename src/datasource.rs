@@ -0,0 +1,336 @@
+//!
+//! Every kind of data the crate can parse, behind one trait
+//!
+//! A parser implements [DataSource] and gets added to [registry], then `inventory`, `timeline`
+//! and `export records` pick it up without any changes to the CLI.
+//!
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use rayon::prelude::*;
+use serde::Serialize;
+
+use crate::activity::albums::AlbumsSource;
+use crate::activity::bug_bounty::BugBountySource;
+use crate::activity::messages::MessagesSource;
+use crate::activity::posts::PostsSource;
+use crate::source::Export;
+use crate::{MagicError, OutputFormat, Skippable};
+
+/// Something that happened, from any of the sources
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct TimelineEvent {
+    pub timestamp: DateTime<Utc>,
+    /// The [DataSource::name] it came from
+    pub source: &'static str,
+    pub actor: Option<String>,
+    pub summary: String,
+}
+
+pub trait DataSource: Skippable + Sync {
+    type Record: Serialize + Send;
+
+    /// Short name, used on the command line and for file names
+    fn name(&self) -> &'static str;
+
+    /// Is this one of the files this source parses
+    fn handles(&self, path: &Path) -> bool;
+
+    /// The files in the export this source parses
+    fn discover(&self, export: &Export) -> Vec<PathBuf> {
+        export
+            .files(&self.path())
+            .into_iter()
+            .filter(|path| self.handles(path))
+            .collect()
+    }
+
+    /// Parse one of the files from [DataSource::discover]. The registry uses whatever lenient
+    /// parsing a source has, leaving out records that don't fit rather than failing.
+    fn parse(&self, export: &Export, path: &Path) -> Result<Vec<Self::Record>, MagicError>;
+
+    /// What a record looks like on the timeline, `None` to leave it off
+    fn event(&self, path: &Path, record: &Self::Record) -> Option<TimelineEvent>;
+
+    /// Counts worth showing for the records. This is called per file and the counts added up,
+    /// so they have to be counts.
+    fn stats(&self, records: &[Self::Record]) -> BTreeMap<String, usize> {
+        BTreeMap::from([("records".to_string(), records.len())])
+    }
+}
+
+/// How many records were written, and the source's stats for them
+pub type Written = (usize, BTreeMap<String, usize>);
+
+/// A [DataSource] with the record type hidden, so they can all go in the [registry]
+pub trait AnyDataSource: Sync {
+    fn name(&self) -> &'static str;
+    fn path(&self) -> PathBuf;
+    fn skippable(&self, export: &Export) -> bool;
+    fn handles(&self, path: &Path) -> bool;
+    fn discover(&self, export: &Export) -> Vec<PathBuf>;
+    /// Writes every record as a JSON array, a few files at a time so they're never all in memory
+    fn write_records(&self, export: &Export, writer: &mut dyn Write)
+        -> Result<Written, MagicError>;
+    fn timeline(&self, export: &Export) -> Vec<TimelineEvent>;
+}
+
+/// Parses one file for the registry. A file that won't parse at all is reported and left out,
+/// so it doesn't stop every other file and source.
+fn parse_file<S: DataSource>(source: &S, export: &Export, path: &Path) -> Vec<S::Record> {
+    source.parse(export, path).unwrap_or_else(|err| {
+        eprintln!(
+            "Skipping {} for {}: {:?}",
+            path.display(),
+            DataSource::name(source),
+            err
+        );
+        Vec::new()
+    })
+}
+
+impl<S: DataSource> AnyDataSource for S {
+    fn name(&self) -> &'static str {
+        DataSource::name(self)
+    }
+
+    fn path(&self) -> PathBuf {
+        Skippable::path(self)
+    }
+
+    fn skippable(&self, export: &Export) -> bool {
+        Skippable::skippable(self, export)
+    }
+
+    fn handles(&self, path: &Path) -> bool {
+        DataSource::handles(self, path)
+    }
+
+    fn discover(&self, export: &Export) -> Vec<PathBuf> {
+        DataSource::discover(self, export)
+    }
+
+    fn write_records(
+        &self,
+        export: &Export,
+        writer: &mut dyn Write,
+    ) -> Result<Written, MagicError> {
+        let io_error = |err: std::io::Error| MagicError::Generic(err.to_string());
+        let mut count = 0;
+        let mut stats = DataSource::stats(self, &[]);
+        writer.write_all(b"[").map_err(io_error)?;
+        // One file per thread at a time, then written in order before parsing the next lot
+        for paths in self.discover(export).chunks(rayon::current_num_threads()) {
+            let parsed: Vec<Vec<S::Record>> = paths
+                .par_iter()
+                .map(|path| parse_file(self, export, path))
+                .collect();
+            for records in parsed {
+                for (stat, n) in DataSource::stats(self, &records) {
+                    *stats.entry(stat).or_default() += n;
+                }
+                for record in records {
+                    let separator: &[u8] = if count == 0 { b"\n" } else { b",\n" };
+                    writer.write_all(separator).map_err(io_error)?;
+                    serde_json::to_writer_pretty(&mut *writer, &record)
+                        .map_err(|err| MagicError::Generic(err.to_string()))?;
+                    count += 1;
+                }
+            }
+        }
+        writer.write_all(b"\n]\n").map_err(io_error)?;
+        Ok((count, stats))
+    }
+
+    fn timeline(&self, export: &Export) -> Vec<TimelineEvent> {
+        self.discover(export)
+            .par_iter()
+            .flat_map_iter(|path| {
+                parse_file(self, export, path)
+                    .iter()
+                    .filter_map(|record| self.event(path, record))
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+}
+
+/// Every source there's a parser for
+pub fn registry() -> Vec<&'static dyn AnyDataSource> {
+    vec![
+        &MessagesSource,
+        &BugBountySource,
+        &PostsSource,
+        &AlbumsSource,
+    ]
+}
+
+/// The sources with these names, or all of them if there aren't any
+pub fn select_sources(names: &[String]) -> Result<Vec<&'static dyn AnyDataSource>, MagicError> {
+    let sources = registry();
+    if let Some(unknown) = names
+        .iter()
+        .find(|name| !sources.iter().any(|source| source.name() == name.as_str()))
+    {
+        let known: Vec<&str> = sources.iter().map(|source| source.name()).collect();
+        return Err(MagicError::Generic(format!(
+            "No source called {}, there's {}",
+            unknown,
+            known.join(", ")
+        )));
+    }
+    Ok(sources
+        .into_iter()
+        .filter(|source| names.is_empty() || names.iter().any(|name| name == source.name()))
+        .collect())
+}
+
+/// The sources that have something in this export, saying which don't
+fn available_sources(
+    export: &Export,
+    names: &[String],
+) -> Result<Vec<&'static dyn AnyDataSource>, MagicError> {
+    Ok(select_sources(names)?
+        .into_iter()
+        .filter(|source| {
+            let skip = source.skippable(export) || !export.exists(&source.path());
+            if skip {
+                eprintln!("No data for {}", source.name());
+            }
+            !skip
+        })
+        .collect())
+}
+
+/// Everything from every source, oldest first
+pub fn timeline(
+    export: &Export,
+    sources: &[String],
+    format: OutputFormat,
+    output: Option<PathBuf>,
+) -> Result<(), MagicError> {
+    let mut events = Vec::new();
+    for source in available_sources(export, sources)? {
+        events.extend(source.timeline(export));
+    }
+    events.sort_by_key(|event| event.timestamp);
+
+    let writer: Box<dyn Write> = match &output {
+        Some(path) => {
+            Box::new(File::create(path).map_err(|err| MagicError::Generic(err.to_string()))?)
+        }
+        None => Box::new(std::io::stdout()),
+    };
+    match format {
+        OutputFormat::Json => serde_json::to_writer_pretty(writer, &events)
+            .map_err(|err| MagicError::Generic(err.to_string())),
+        OutputFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(writer);
+            for event in events.iter() {
+                writer
+                    .serialize(event)
+                    .map_err(|err| MagicError::Generic(err.to_string()))?;
+            }
+            writer
+                .flush()
+                .map_err(|err| MagicError::Generic(err.to_string()))
+        }
+    }
+}
+
+/// Writes each source's records to `<name>.json` in `output`, and prints its stats
+pub fn export_records(
+    export: &Export,
+    sources: &[String],
+    output: &Path,
+) -> Result<(), MagicError> {
+    std::fs::create_dir_all(output)
+        .map_err(|err| MagicError::Generic(format!("{}: {}", output.display(), err)))?;
+    for source in available_sources(export, sources)? {
+        let path = output.join(format!("{}.json", source.name()));
+        let file = File::create(&path)
+            .map_err(|err| MagicError::Generic(format!("{}: {}", path.display(), err)))?;
+        let mut writer = BufWriter::new(file);
+        let (count, stats) = source.write_records(export, &mut writer)?;
+        writer
+            .flush()
+            .map_err(|err| MagicError::Generic(format!("{}: {}", path.display(), err)))?;
+        println!(
+            "Wrote {} {} records to {}",
+            count,
+            source.name(),
+            path.display()
+        );
+        for (stat, count) in stats {
+            println!("  {}: {}", stat, count);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::{registry, select_sources};
+    use crate::generate::{generate_export, GeneratorConfig};
+    use crate::source::Export;
+    use crate::test_util::temp_dir;
+
+    #[test]
+    fn test_registry() {
+        assert!(select_sources(&["nope".to_string()]).is_err());
+        assert_eq!(select_sources(&[]).unwrap().len(), registry().len());
+
+        let temp = temp_dir();
+        let root = temp.path().to_path_buf();
+        let config = GeneratorConfig {
+            threads: 2,
+            messages: 30,
+            attachments: 4,
+        };
+        generate_export(&root, &config).unwrap();
+        let export = Export::load(std::slice::from_ref(&root)).unwrap();
+
+        let messages = select_sources(&["messages".to_string()]).unwrap()[0];
+        assert_eq!(messages.discover(&export).len(), 2);
+        let mut written = Vec::new();
+        let (count, stats) = messages.write_records(&export, &mut written).unwrap();
+        assert_eq!(count, 60);
+        assert_eq!(stats["messages"], 60);
+        let records: Vec<Value> = serde_json::from_slice(&written).unwrap();
+        assert_eq!(records.len(), 60);
+        assert!(records
+            .iter()
+            .any(|record| record["thread"] == "inbox/person1_1000"));
+        assert_eq!(records[0]["id"].as_str().unwrap().len(), 32);
+        let events = messages.timeline(&export);
+        assert_eq!(events.len(), 60);
+        assert!(events.iter().all(|event| event.source == "messages"));
+
+        // an older export with more of each thread, only the messages it adds are included
+        let older_temp = temp_dir();
+        let older = older_temp.path().to_path_buf();
+        let older_config = GeneratorConfig {
+            messages: 40,
+            ..config
+        };
+        generate_export(&older, &older_config).unwrap();
+        let merged = Export::load(std::slice::from_ref(&root))
+            .unwrap()
+            .merge(vec![Export::load(std::slice::from_ref(&older)).unwrap()]);
+        let (count, _) = messages.write_records(&merged, &mut Vec::new()).unwrap();
+        assert_eq!(count, 80);
+        assert_eq!(messages.timeline(&merged).len(), 80);
+
+        // a file that doesn't parse is left out rather than failing the source
+        let broken = messages.discover(&export)[0].clone();
+        std::fs::write(root.join(&broken), "not json").unwrap();
+        assert_eq!(messages.timeline(&export).len(), 30);
+        let (count, _) = messages.write_records(&export, &mut Vec::new()).unwrap();
+        assert_eq!(count, 30);
+    }
+}
@@ -0,0 +1,316 @@
+//!
+//! Comparing two exports, to see what's been added or deleted between downloads
+//!
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::activity::messages::id::MessageId;
+use crate::activity::messages::stream::{read_message_header, stream_message_file};
+use crate::activity::messages::{find_message_files, thread_path_from_file};
+use crate::activity::ActivityTypes;
+use crate::source::{is_media_file, Export};
+use crate::{Folders, MagicError, Skippable};
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MissingReason {
+    /// There's no trace of it in the newer export
+    Deleted,
+    /// The newer export has it marked as unsent
+    Unsent,
+}
+
+#[derive(Serialize, Debug)]
+pub struct MissingMessage {
+    pub id: MessageId,
+    pub thread: String,
+    pub sender: String,
+    pub timestamp: Option<DateTime<Utc>>,
+    pub content: Option<String>,
+    pub reason: MissingReason,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ParticipationChange {
+    pub thread: String,
+    pub title: String,
+    pub was_participant: bool,
+    pub is_participant: bool,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct ExportDiff {
+    pub folders_added: Vec<String>,
+    pub folders_removed: Vec<String>,
+    pub threads_added: Vec<String>,
+    pub threads_removed: Vec<String>,
+    pub messages_missing: Vec<MissingMessage>,
+    pub participation_changed: Vec<ParticipationChange>,
+    pub media_missing: Vec<PathBuf>,
+}
+
+/// Message files in an export, by thread path
+fn threads(export: &Export) -> BTreeMap<String, Vec<PathBuf>> {
+    let mut threads: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+    for file in find_message_files(export, &ActivityTypes::Messages.path()) {
+        threads
+            .entry(thread_path_from_file(&file))
+            .or_default()
+            .push(file);
+    }
+    threads
+}
+
+/// Finds the messages in `old_files` that aren't in `new_files`
+fn diff_thread(
+    old: &Export,
+    old_files: &[PathBuf],
+    new: &Export,
+    new_files: &[PathBuf],
+    thread: &str,
+) -> Result<Vec<MissingMessage>, MagicError> {
+    let mut seen = HashSet::new();
+    let mut unsent = HashSet::new();
+    for file in new_files {
+        stream_message_file(new, file, |message| {
+            if message.is_unsent == Some(true) {
+                unsent.insert((message.sender_name.clone(), message.timestamp_ms));
+            }
+            seen.insert(message.id);
+            Ok(())
+        })?;
+    }
+
+    let mut missing = Vec::new();
+    for file in old_files {
+        stream_message_file(old, file, |message| {
+            if seen.contains(&message.id) {
+                return Ok(());
+            }
+            let reason = if unsent.contains(&(message.sender_name.clone(), message.timestamp_ms)) {
+                MissingReason::Unsent
+            } else {
+                MissingReason::Deleted
+            };
+            missing.push(MissingMessage {
+                id: message.id,
+                thread: thread.to_string(),
+                sender: message.sender_name,
+                timestamp: DateTime::<Utc>::from_timestamp_millis(message.timestamp_ms as i64),
+                content: message.content,
+                reason,
+            });
+            Ok(())
+        })?;
+    }
+    Ok(missing)
+}
+
+/// Compare two exports, reporting what's in `old` that isn't in `new` and the other way around
+pub fn diff_exports(old: &Export, new: &Export) -> Result<ExportDiff, MagicError> {
+    let mut diff = ExportDiff::default();
+
+    for folder in enum_iterator::all::<Folders>() {
+        let path = Path::new(folder.path());
+        match (old.exists(path), new.exists(path)) {
+            (true, false) => diff.folders_removed.push(folder.path().to_string()),
+            (false, true) => diff.folders_added.push(folder.path().to_string()),
+            _ => {}
+        }
+    }
+
+    let old_threads = threads(old);
+    let new_threads = threads(new);
+    diff.threads_added = new_threads
+        .keys()
+        .filter(|thread| !old_threads.contains_key(*thread))
+        .cloned()
+        .collect();
+    diff.threads_removed = old_threads
+        .keys()
+        .filter(|thread| !new_threads.contains_key(*thread))
+        .cloned()
+        .collect();
+
+    for (thread, old_files) in old_threads.iter() {
+        let new_files = match new_threads.get(thread) {
+            Some(new_files) => new_files,
+            None => continue,
+        };
+        let old_header = read_message_header(old, &old_files[0])?;
+        let new_header = read_message_header(new, &new_files[0])?;
+        if old_header.is_still_participant != new_header.is_still_participant {
+            diff.participation_changed.push(ParticipationChange {
+                thread: thread.clone(),
+                title: new_header.title,
+                was_participant: old_header.is_still_participant,
+                is_participant: new_header.is_still_participant,
+            });
+        }
+        diff.messages_missing
+            .extend(diff_thread(old, old_files, new, new_files, thread)?);
+    }
+
+    let new_files: BTreeSet<PathBuf> = new.files(Path::new("")).into_iter().collect();
+    diff.media_missing = old
+        .files(Path::new(""))
+        .into_iter()
+        .filter(|file| is_media_file(file) && !new_files.contains(file))
+        .collect();
+
+    Ok(diff)
+}
+
+/// Print the diff for a person to read
+pub fn print_diff(diff: &ExportDiff) {
+    for folder in diff.folders_added.iter() {
+        println!("Folder added: {}", folder);
+    }
+    for folder in diff.folders_removed.iter() {
+        println!("Folder removed: {}", folder);
+    }
+    for thread in diff.threads_added.iter() {
+        println!("Thread added: {}", thread);
+    }
+    for thread in diff.threads_removed.iter() {
+        println!("Thread removed: {}", thread);
+    }
+    for change in diff.participation_changed.iter() {
+        println!(
+            "Participation changed in {} ({}): {} -> {}",
+            change.title, change.thread, change.was_participant, change.is_participant
+        );
+    }
+    for message in diff.messages_missing.iter() {
+        println!(
+            "Message {} {:?} from {} in {} at {}: {}",
+            message.id,
+            message.reason,
+            message.sender,
+            message.thread,
+            message
+                .timestamp
+                .map(|ts| ts.to_rfc3339())
+                .unwrap_or_default(),
+            message.content.as_deref().unwrap_or_default()
+        );
+    }
+    for media in diff.media_missing.iter() {
+        println!("Media missing: {}", media.display());
+    }
+    println!(
+        "{} threads added, {} removed, {} messages missing, {} participation changes, {} media files missing",
+        diff.threads_added.len(),
+        diff.threads_removed.len(),
+        diff.messages_missing.len(),
+        diff.participation_changed.len(),
+        diff.media_missing.len()
+    );
+}
+
+pub fn export_diff(old: &Path, new: &Path, json: bool) -> Result<(), MagicError> {
+    let old = Export::load(&[old.to_path_buf()])?;
+    let new = Export::load(&[new.to_path_buf()])?;
+    let diff = diff_exports(&old, &new)?;
+    if json {
+        let output = serde_json::to_string_pretty(&diff)
+            .map_err(|err| MagicError::Generic(err.to_string()))?;
+        println!("{}", output);
+    } else {
+        print_diff(&diff);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use serde_json::{json, Value};
+
+    use super::{diff_exports, MissingReason};
+    use crate::source::{DirectorySource, Export};
+    use crate::test_util::{message_file, temp_dir, write_file};
+
+    fn write_thread(root: &Path, is_still_participant: bool, messages: Value) {
+        let mut file = message_file("inbox/bob_1", messages);
+        file["is_still_participant"] = json!(is_still_participant);
+        write_file(
+            root,
+            "your_activity_across_facebook/messages/inbox/bob_1/message_1.json",
+            file.to_string(),
+        );
+    }
+
+    #[test]
+    fn test_diff_exports() {
+        let temp = temp_dir();
+        let folder = temp.path().to_path_buf();
+        let (old, new) = (folder.join("old"), folder.join("new"));
+        write_thread(
+            &old,
+            true,
+            json!([
+                {"sender_name": "Bob", "timestamp_ms": 2, "content": "kept", "is_geoblocked_for_viewer": false},
+                {"sender_name": "Bob", "timestamp_ms": 1, "content": "oops", "is_geoblocked_for_viewer": false}
+            ]),
+        );
+        write_thread(
+            &new,
+            false,
+            json!([
+                {"sender_name": "Bob", "timestamp_ms": 2, "content": "kept", "is_geoblocked_for_viewer": false},
+                {"sender_name": "Bob", "timestamp_ms": 1, "is_unsent": true, "is_geoblocked_for_viewer": false}
+            ]),
+        );
+        std::fs::write(
+            old.join("your_activity_across_facebook/messages/inbox/bob_1/1.jpg"),
+            "",
+        )
+        .unwrap();
+
+        let diff = diff_exports(
+            &Export::from_source(DirectorySource::new(&old)),
+            &Export::from_source(DirectorySource::new(&new)),
+        )
+        .unwrap();
+        assert!(diff.threads_added.is_empty());
+        assert_eq!(diff.messages_missing.len(), 1);
+        assert_eq!(diff.messages_missing[0].reason, MissingReason::Unsent);
+        assert_eq!(diff.participation_changed.len(), 1);
+        assert_eq!(diff.media_missing.len(), 1);
+    }
+
+    #[test]
+    fn test_diff_across_layouts() {
+        let temp = temp_dir();
+        let folder = temp.path().to_path_buf();
+        let (old, new) = (folder.join("old"), folder.join("new"));
+        let thread = |root: &Path, messages: &str| {
+            let file = message_file(
+                "inbox/bob_1",
+                json!([{
+                    "sender_name": "Bob",
+                    "timestamp_ms": 1,
+                    "photos": [{"uri": format!("{}/inbox/bob_1/photos/1.jpg", messages)}],
+                    "is_geoblocked_for_viewer": false
+                }]),
+            );
+            let path = Path::new(messages).join("inbox/bob_1/message_1.json");
+            write_file(root, path, file.to_string());
+        };
+        thread(&old, "messages");
+        thread(&new, "your_activity_across_facebook/messages");
+
+        let diff = diff_exports(
+            &Export::from_source(DirectorySource::new(&old)),
+            &Export::from_source(DirectorySource::new(&new)),
+        )
+        .unwrap();
+        assert!(diff.messages_missing.is_empty());
+        assert!(diff.threads_added.is_empty() && diff.threads_removed.is_empty());
+    }
+}
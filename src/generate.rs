@@ -0,0 +1,366 @@
+//!
+//! Generating a fake export, so there's something to test and benchmark against that isn't
+//! anyone's real data
+//!
+//! Every optional message field turns up somewhere, and every folder a real export has is
+//! there, with `no-data.txt` in the ones that are empty.
+//!
+use std::path::{Path, PathBuf};
+
+use serde_json::{json, Value};
+
+use crate::activity::messages::anonymize::placeholder;
+use crate::activity::messages::threads::ThreadCategory;
+use crate::activity::ActivityTypes;
+use crate::{Folders, MagicError, Skippable};
+
+/// The name of the person the export belongs to
+pub static OWNER: &str = "Test User";
+
+/// Facebook splits threads over several files, this makes sure that gets exercised
+static MESSAGES_PER_FILE: usize = 50;
+
+/// 2020-01-01T00:00:00Z
+static START_MS: u64 = 1_577_836_800_000;
+
+static WORDS: &[&str] = &[
+    "lorem",
+    "ipsum",
+    "dolor",
+    "sit",
+    "amet",
+    "consectetur",
+    "adipiscing",
+    "elit",
+    "sed",
+    "do",
+    "eiusmod",
+    "tempor",
+    "incididunt",
+    "ut",
+    "labore",
+    "et",
+    "dolore",
+    "magna",
+    "aliqua",
+];
+
+#[derive(Debug, Clone)]
+pub struct GeneratorConfig {
+    pub threads: usize,
+    /// Per thread
+    pub messages: usize,
+    /// Per thread, messages that'd have one become text once they run out
+    pub attachments: usize,
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> Self {
+        Self {
+            threads: 4,
+            messages: 120,
+            attachments: 20,
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct GeneratedExport {
+    pub threads: usize,
+    pub messages: usize,
+    pub attachments: usize,
+}
+
+fn write(path: &Path, contents: &[u8]) -> Result<(), MagicError> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|err| MagicError::Generic(format!("{}: {}", parent.display(), err)))?;
+    }
+    std::fs::write(path, contents)
+        .map_err(|err| MagicError::Generic(format!("{}: {}", path.display(), err)))
+}
+
+/// Builds up one thread's messages and the attachment files they point at
+struct ThreadGenerator {
+    folder: PathBuf,
+    participants: Vec<String>,
+    attachments_left: usize,
+    attachments: Vec<PathBuf>,
+}
+
+impl ThreadGenerator {
+    fn text(&self, index: usize) -> String {
+        (0..3 + index % 5)
+            .map(|word| WORDS[(index * 7 + word * 3) % WORDS.len()])
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Uses up one of the thread's attachments, `None` once they've run out
+    fn attachment(&mut self, folder: &str, index: usize, extension: &str) -> Option<String> {
+        if self.attachments_left == 0 {
+            return None;
+        }
+        self.attachments_left -= 1;
+        let path = self
+            .folder
+            .join(folder)
+            .join(format!("{}_{}.{}", folder, index, extension));
+        self.attachments.push(path.clone());
+        Some(path.display().to_string())
+    }
+
+    fn message(&mut self, index: usize, timestamp_ms: u64) -> Value {
+        let sender = self.participants[index % self.participants.len()].clone();
+        let timestamp_s = timestamp_ms / 1000;
+        let mut message = json!({
+            "sender_name": sender,
+            "timestamp_ms": timestamp_ms,
+            "is_geoblocked_for_viewer": false,
+        });
+
+        let attachment = match index % 10 {
+            1 => self.attachment("photos", index, "jpg").map(|uri| {
+                json!({"photos": [{"uri": uri, "creation_timestamp": timestamp_s}]})
+            }),
+            2 => self.attachment("videos", index, "mp4").map(|uri| {
+                json!({"videos": [{"uri": uri, "creation_timestamp": timestamp_s}]})
+            }),
+            3 => self
+                .attachment("gifs", index, "gif")
+                .map(|uri| json!({"gifs": [{"uri": uri}]})),
+            4 => self.attachment("stickers_used", index, "png").map(|uri| {
+                json!({"sticker": {"uri": uri, "ai_stickers": [{"input": self.text(index)}]}})
+            }),
+            5 => self.attachment("files", index, "pdf").map(|uri| {
+                json!({"files": [{"uri": uri, "creation_timestamp": timestamp_s, "title": "notes.pdf"}]})
+            }),
+            6 => self.attachment("audio", index, "mp4").map(|uri| {
+                json!({"audio_files": [{"uri": uri, "creation_timestamp": timestamp_s}]})
+            }),
+            7 => Some(json!({
+                "share": {"link": format!("https://example.com/articles/{}", index), "share_text": self.text(index)},
+                "content": format!("https://example.com/articles/{}", index),
+            })),
+            8 if index % 20 == 8 => Some(json!({
+                "call_duration": 0,
+                "missed": true,
+                "content": format!("{} missed your call.", OWNER),
+            })),
+            8 => Some(json!({
+                "call_duration": 60 + index as u64,
+                "missed": false,
+                "content": "The call ended.",
+            })),
+            9 => Some(json!({"is_unsent": true})),
+            _ => None,
+        };
+        let fields = attachment.unwrap_or_else(|| json!({"content": self.text(index)}));
+        message
+            .as_object_mut()
+            .unwrap()
+            .extend(fields.as_object().unwrap().clone());
+
+        if index.is_multiple_of(4) {
+            let actor = self.participants[(index + 1) % self.participants.len()].clone();
+            message["reactions"] =
+                json!([{"reaction": "\u{00e2}\u{009d}\u{00a4}", "actor": actor}]);
+        }
+        if index.is_multiple_of(5) {
+            message["ip"] = json!(format!("192.0.2.{}", index % 254 + 1));
+        }
+        message
+    }
+}
+
+/// Writes one thread, returning how many messages and attachments it has
+fn generate_thread(
+    root: &Path,
+    index: usize,
+    config: &GeneratorConfig,
+) -> Result<(usize, usize), MagicError> {
+    let categories = [
+        ThreadCategory::Inbox,
+        ThreadCategory::ArchivedThreads,
+        ThreadCategory::FilteredThreads,
+        ThreadCategory::MessageRequests,
+    ];
+    let category = categories[index % categories.len()];
+    let name = format!("Person {}", index + 1);
+    let is_group = index % 3 == 2;
+    let mut participants = vec![OWNER.to_string(), name.clone()];
+    if is_group {
+        participants.push(format!("Person {}b", index + 1));
+        participants.push(format!("Person {}c", index + 1));
+    }
+    let title = match is_group {
+        true => format!("Group {}", index + 1),
+        false => name,
+    };
+    let folder_name = format!("{}_{}", title.to_lowercase().replace(' ', ""), 1000 + index);
+    let folder = ActivityTypes::Messages
+        .path()
+        .join(category.path())
+        .join(&folder_name);
+
+    let mut thread = ThreadGenerator {
+        folder: folder.clone(),
+        participants: participants.clone(),
+        attachments_left: config.attachments,
+        attachments: Vec::new(),
+    };
+    let thread_start = START_MS + index as u64 * 86_400_000;
+    let mut messages: Vec<Value> = (0..config.messages)
+        .map(|message| thread.message(message, thread_start + message as u64 * 3_600_000))
+        .collect();
+    if is_group && !messages.is_empty() {
+        messages[0]["content"] = json!(format!(
+            "{} added {} and {} to the group.",
+            OWNER, participants[2], participants[3]
+        ));
+        messages[0]["sender_name"] = json!(OWNER);
+    }
+    // newest first, like Facebook writes them
+    messages.reverse();
+
+    let image = match is_group {
+        true => thread.attachment("photos", config.messages, "jpg"),
+        false => None,
+    };
+    let file_count = messages.len().div_ceil(MESSAGES_PER_FILE).max(1);
+    for file_number in 0..file_count {
+        let chunk: Vec<Value> = messages
+            .iter()
+            .skip(file_number * MESSAGES_PER_FILE)
+            .take(MESSAGES_PER_FILE)
+            .cloned()
+            .collect();
+        let mut file = json!({
+            "participants": participants.iter().map(|name| json!({"name": name})).collect::<Vec<_>>(),
+            "messages": chunk,
+            "title": title,
+            "is_still_participant": true,
+            "thread_path": format!("{}/{}", category.path(), folder_name),
+            "magic_words": [{
+                "magic_word": "congrats",
+                "creation_timestamp_ms": thread_start,
+                "animation_emoji": "\u{00f0}\u{009f}\u{008e}\u{0089}",
+            }],
+        });
+        if let Some(image) = &image {
+            file["image"] = json!({"uri": image, "creation_timestamp": thread_start / 1000});
+        }
+        if is_group {
+            file["joinable_mode"] = json!({"mode": 1, "link": format!("https://m.me/j/{}", index)});
+        }
+        let contents = serde_json::to_string_pretty(&file)
+            .map_err(|err| MagicError::Generic(err.to_string()))?;
+        let path = root
+            .join(&folder)
+            .join(format!("message_{}.json", file_number + 1));
+        write(&path, contents.as_bytes())?;
+    }
+
+    for attachment in thread.attachments.iter() {
+        write(&root.join(attachment), placeholder(attachment))?;
+    }
+    Ok((messages.len(), thread.attachments.len()))
+}
+
+/// Writes a fake export into `root`, which is created if it's not there
+pub fn generate_export(
+    root: &Path,
+    config: &GeneratorConfig,
+) -> Result<GeneratedExport, MagicError> {
+    for folder in enum_iterator::all::<Folders>() {
+        if folder != Folders::YourActivityAcrossFacebook {
+            write(&root.join(folder.path()).join("no-data.txt"), b"")?;
+        }
+    }
+    for activity in enum_iterator::all::<ActivityTypes>() {
+        if activity != ActivityTypes::Messages {
+            write(&root.join(activity.path()).join("no-data.txt"), b"")?;
+        }
+    }
+
+    let mut summary = GeneratedExport::default();
+    for index in 0..config.threads {
+        let (messages, attachments) = generate_thread(root, index, config)?;
+        summary.threads += 1;
+        summary.messages += messages;
+        summary.attachments += attachments;
+    }
+    Ok(summary)
+}
+
+pub fn generate(root: &Path, config: &GeneratorConfig) -> Result<(), MagicError> {
+    let summary = generate_export(root, config)?;
+    println!(
+        "Wrote {} threads, {} messages and {} attachments to {}",
+        summary.threads,
+        summary.messages,
+        summary.attachments,
+        root.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{generate_export, GeneratorConfig};
+    use crate::activity::messages::kind::MessageKind;
+    use crate::activity::messages::{find_message_files, MessageFileParser};
+    use crate::activity::ActivityTypes;
+    use crate::source::Export;
+    use crate::test_util::temp_dir;
+    use crate::{Folders, Skippable};
+
+    #[test]
+    fn test_generate_export() {
+        let temp = temp_dir();
+        let root = temp.path().to_path_buf();
+        let config = GeneratorConfig {
+            threads: 3,
+            messages: 60,
+            attachments: 8,
+        };
+        let summary = generate_export(&root, &config).unwrap();
+        assert_eq!(summary.threads, 3);
+        assert_eq!(summary.messages, 180);
+
+        let export = Export::load(std::slice::from_ref(&root)).unwrap();
+        for folder in enum_iterator::all::<Folders>() {
+            assert!(export.exists(Path::new(folder.path())));
+        }
+        assert!(ActivityTypes::BugBounty.skippable(&export));
+        assert!(!ActivityTypes::Messages.skippable(&export));
+
+        let files = find_message_files(&export, &ActivityTypes::Messages.path());
+        assert_eq!(files.len(), 6);
+        let mut kinds = Vec::new();
+        for file in files.iter() {
+            let parsed = MessageFileParser::from_export(&export, file).unwrap();
+            for message in parsed.messages.iter() {
+                for uri in message.attachment_uris() {
+                    assert!(export.exists(Path::new(uri)), "{} is missing", uri);
+                }
+                kinds.push(message.kind());
+            }
+        }
+        assert_eq!(kinds.len(), 180);
+        for kind in [
+            MessageKind::Photo,
+            MessageKind::Sticker,
+            MessageKind::MissedCall,
+            MessageKind::Unsent,
+            MessageKind::Share,
+        ] {
+            assert!(kinds.contains(&kind), "no {:?}", kind);
+        }
+        assert!(kinds
+            .iter()
+            .any(|kind| matches!(kind, MessageKind::System(_))));
+    }
+}
@@ -0,0 +1,255 @@
+//!
+//! What's in an export, file by file, and how much of it this crate does anything with
+//!
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use enum_iterator::Sequence;
+use rayon::prelude::*;
+use serde::Serialize;
+
+use crate::activity::messages::threads::ThreadCategory;
+use crate::activity::ActivityTypes;
+use crate::datasource::registry;
+use crate::source::{is_media_file, Export};
+use crate::{Folders, MagicError, Skippable};
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Sequence)]
+#[serde(rename_all = "snake_case")]
+pub enum FileClass {
+    /// Read by one of the parsers
+    Parsed,
+    /// In a folder we know about, but nothing reads it yet
+    Unsupported,
+    /// Photos, videos and audio, and anything else sent as an attachment
+    Media,
+    /// `no-data.txt`, which Facebook puts in empty folders
+    Placeholder,
+    /// Not in any folder we know about
+    Unknown,
+}
+
+impl FileClass {
+    pub fn classify(path: &Path) -> Self {
+        if path.file_name().is_some_and(|name| name == "no-data.txt") {
+            return FileClass::Placeholder;
+        }
+        if registry().iter().any(|source| source.handles(path)) {
+            return FileClass::Parsed;
+        }
+        // everything else in a thread's folder was sent in it
+        if in_thread_folder(path) {
+            return FileClass::Media;
+        }
+        if is_media_file(path) {
+            return FileClass::Media;
+        }
+        if enum_iterator::all::<Folders>().any(|folder| path.starts_with(folder.path())) {
+            return FileClass::Unsupported;
+        }
+        FileClass::Unknown
+    }
+
+    pub fn describe(&self) -> &'static str {
+        match self {
+            FileClass::Parsed => "parsed",
+            FileClass::Unsupported => "not supported yet",
+            FileClass::Media => "media",
+            FileClass::Placeholder => "no-data placeholders",
+            FileClass::Unknown => "unknown",
+        }
+    }
+}
+
+/// Is it somewhere in a thread's folder, eg `messages/inbox/bob_1/files/notes.pdf`, rather than
+/// one of the files alongside the threads
+fn in_thread_folder(path: &Path) -> bool {
+    ThreadCategory::from_thread_folder(path).is_some()
+        && path
+            .strip_prefix(ActivityTypes::Messages.path())
+            .is_ok_and(|relative| relative.components().count() > 2)
+}
+
+#[derive(Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Tally {
+    pub files: usize,
+    pub bytes: u64,
+}
+
+impl Tally {
+    fn add(&mut self, bytes: u64) {
+        self.files += 1;
+        self.bytes += bytes;
+    }
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct Inventory {
+    /// Which generation of the export it is, `None` if it's not one we know
+    pub layout: Option<&'static str>,
+    pub total: Tally,
+    pub classes: BTreeMap<FileClass, Tally>,
+    /// Files nothing reads yet, by the folder they're in, eg `your_activity_across_facebook/comments_and_reactions`
+    pub unsupported: BTreeMap<PathBuf, Tally>,
+    pub unknown: BTreeMap<PathBuf, Tally>,
+}
+
+impl Inventory {
+    pub fn add(&mut self, path: &Path, bytes: u64) {
+        let class = FileClass::classify(path);
+        self.total.add(bytes);
+        self.classes.entry(class).or_default().add(bytes);
+        let section = || {
+            path.parent()
+                .map(|parent| parent.components().take(2).collect())
+                .unwrap_or_default()
+        };
+        match class {
+            FileClass::Unsupported => self.unsupported.entry(section()).or_default().add(bytes),
+            FileClass::Unknown => self.unknown.entry(section()).or_default().add(bytes),
+            _ => {}
+        }
+    }
+}
+
+pub fn take_inventory(export: &Export) -> Result<Inventory, MagicError> {
+    let sizes = export
+        .files(Path::new(""))
+        .into_par_iter()
+        .map(|path| Ok((export.size(&path)?, path)))
+        .collect::<Result<Vec<(u64, PathBuf)>, MagicError>>()?;
+    let mut inventory = Inventory {
+        layout: export.layout().map(|layout| layout.name()),
+        ..Default::default()
+    };
+    for (size, path) in sizes {
+        inventory.add(&path, size);
+    }
+    Ok(inventory)
+}
+
+fn human_size(bytes: u64) -> String {
+    let units = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{} B", bytes),
+        _ => format!("{:.1} {}", size, units[unit]),
+    }
+}
+
+fn print_tally(name: &str, tally: &Tally) {
+    println!(
+        "  {:<60} {:>7} files {:>10}",
+        name,
+        tally.files,
+        human_size(tally.bytes)
+    );
+}
+
+pub fn inventory(export: &Export, json: bool) -> Result<(), MagicError> {
+    let inventory = take_inventory(export)?;
+    if json {
+        let output = serde_json::to_string_pretty(&inventory)
+            .map_err(|err| MagicError::Generic(err.to_string()))?;
+        println!("{}", output);
+        return Ok(());
+    }
+
+    println!("Layout: {}", inventory.layout.unwrap_or("unrecognised"));
+    for class in enum_iterator::all::<FileClass>() {
+        print_tally(
+            class.describe(),
+            &inventory.classes.get(&class).copied().unwrap_or_default(),
+        );
+    }
+    print_tally("total", &inventory.total);
+    for (heading, sections) in [
+        ("Not supported yet:", &inventory.unsupported),
+        ("Unknown:", &inventory.unknown),
+    ] {
+        if sections.is_empty() {
+            continue;
+        }
+        println!("{}", heading);
+        for (section, tally) in sections.iter() {
+            match section.as_os_str().is_empty() {
+                true => print_tally("(top level)", tally),
+                false => print_tally(&section.display().to_string(), tally),
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::{FileClass, Inventory};
+
+    #[test]
+    fn test_inventory() {
+        let files = [
+            (
+                "your_activity_across_facebook/messages/inbox/bob_1/message_1.json",
+                FileClass::Parsed,
+            ),
+            (
+                "your_activity_across_facebook/messages/inbox/bob_1/photos/1.jpg",
+                FileClass::Media,
+            ),
+            (
+                "your_activity_across_facebook/messages/inbox/bob_1/files/notes.pdf",
+                FileClass::Media,
+            ),
+            (
+                "your_activity_across_facebook/messages/autofill_information.json",
+                FileClass::Unsupported,
+            ),
+            (
+                "your_activity_across_facebook/messages/stickers_used/1.png",
+                FileClass::Media,
+            ),
+            (
+                "your_activity_across_facebook/bug_bounty/no-data.txt",
+                FileClass::Placeholder,
+            ),
+            (
+                "your_activity_across_facebook/posts/your_posts_1.json",
+                FileClass::Parsed,
+            ),
+            (
+                "your_activity_across_facebook/comments_and_reactions/comments.json",
+                FileClass::Unsupported,
+            ),
+            (
+                "connections/friends/your_friends.json",
+                FileClass::Unsupported,
+            ),
+            ("index.html", FileClass::Unknown),
+        ];
+        let mut inventory = Inventory::default();
+        for (path, class) in files {
+            assert_eq!(FileClass::classify(Path::new(path)), class, "{}", path);
+            inventory.add(Path::new(path), 100);
+        }
+
+        assert_eq!(inventory.total.files, 10);
+        assert_eq!(inventory.total.bytes, 1000);
+        assert_eq!(inventory.classes[&FileClass::Unsupported].files, 3);
+        assert_eq!(
+            inventory.unsupported.keys().cloned().collect::<Vec<_>>(),
+            vec![
+                PathBuf::from("connections/friends"),
+                PathBuf::from("your_activity_across_facebook/comments_and_reactions"),
+                PathBuf::from("your_activity_across_facebook/messages"),
+            ]
+        );
+        assert_eq!(inventory.unknown[&PathBuf::new()].files, 1);
+    }
+}
@@ -0,0 +1,158 @@
+//!
+//! Which generation of Facebook's download an export is
+//!
+//! Facebook moves things around every so often, `your_activity_across_facebook` became
+//! `your_facebook_activity`, and before either existed messages were at the top level. The rest
+//! of the crate uses the paths from [Layout::ActivityAcrossFacebook], and an [Export] maps them
+//! onto wherever its layout actually keeps them.
+//!
+//! [Export]: crate::source::Export
+//!
+use std::path::{Path, PathBuf};
+
+use enum_iterator::Sequence;
+
+use crate::source::ExportSource;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Sequence)]
+pub enum Layout {
+    /// Before 2022, with `messages`, `posts` and the like at the top level
+    Legacy,
+    /// 2022 to 2024, the layout the rest of the crate's paths are in
+    #[default]
+    ActivityAcrossFacebook,
+    /// 2024 on
+    FacebookActivity,
+}
+
+impl Layout {
+    /// The folder only this layout has at the top level
+    fn marker(&self) -> &'static str {
+        match self {
+            Layout::Legacy => "messages",
+            Layout::ActivityAcrossFacebook => "your_activity_across_facebook",
+            Layout::FacebookActivity => "your_facebook_activity",
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Layout::Legacy => "legacy (before 2022)",
+            Layout::ActivityAcrossFacebook => "your_activity_across_facebook (2022 to 2024)",
+            Layout::FacebookActivity => "your_facebook_activity (2024 on)",
+        }
+    }
+
+    /// Where things the crate looks for are in this layout, as (crate path, export path).
+    /// More specific paths come first.
+    fn renames(&self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Layout::Legacy => &[
+                ("your_activity_across_facebook/messages", "messages"),
+                ("your_activity_across_facebook/bug_bounty", "bug_bounty"),
+                (
+                    "your_activity_across_facebook/posts/album",
+                    "photos_and_videos/album",
+                ),
+                ("your_activity_across_facebook/posts", "posts"),
+                ("ads_information", "ads_and_businesses"),
+                ("apps_and_websites_off_of_facebook", "apps_and_websites"),
+                ("connections", "friends_and_followers"),
+                ("logged_information", "other_logged_information"),
+                ("personal_information", "profile_information"),
+            ],
+            Layout::ActivityAcrossFacebook => &[],
+            Layout::FacebookActivity => {
+                &[("your_activity_across_facebook", "your_facebook_activity")]
+            }
+        }
+    }
+
+    /// Every top level folder that belongs to a layout we know about
+    pub fn known_top_level() -> Vec<&'static str> {
+        let mut folders: Vec<&str> = enum_iterator::all::<crate::Folders>()
+            .map(|folder| folder.path())
+            .collect();
+        for layout in enum_iterator::all::<Layout>() {
+            folders.push(layout.marker());
+            folders.extend(layout.renames().iter().map(|(_, physical)| *physical));
+        }
+        folders
+    }
+
+    /// Works out the layout from the top level folders, `None` if it's not one we know
+    pub fn detect(sources: &[&dyn ExportSource]) -> Option<Layout> {
+        // newest first, in case a download somehow has more than one
+        let mut layouts: Vec<Layout> = enum_iterator::all::<Layout>().collect();
+        layouts.reverse();
+        layouts.into_iter().find(|layout| {
+            sources
+                .iter()
+                .any(|source| source.exists(Path::new(layout.marker())))
+        })
+    }
+
+    /// A path the crate uses, eg `your_activity_across_facebook/messages/inbox`, to where it is
+    /// in this layout
+    pub fn to_physical(&self, path: &Path) -> PathBuf {
+        for (logical, physical) in self.renames() {
+            if let Ok(rest) = path.strip_prefix(logical) {
+                return Path::new(physical).join(rest);
+            }
+        }
+        path.to_path_buf()
+    }
+
+    /// The other way around from [Layout::to_physical]
+    pub fn to_logical(&self, path: &Path) -> PathBuf {
+        for (logical, physical) in self.renames() {
+            if let Ok(rest) = path.strip_prefix(physical) {
+                return Path::new(logical).join(rest);
+            }
+        }
+        path.to_path_buf()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::Layout;
+    use crate::activity::ActivityTypes;
+    use crate::source::DirectorySource;
+    use crate::test_util::temp_dir;
+    use crate::Skippable;
+
+    #[test]
+    fn test_layout() {
+        let messages = ActivityTypes::Messages
+            .path()
+            .join("inbox/bob_1/message_1.json");
+        assert_eq!(
+            Layout::FacebookActivity.to_physical(&messages),
+            Path::new("your_facebook_activity/messages/inbox/bob_1/message_1.json")
+        );
+        assert_eq!(
+            Layout::Legacy.to_physical(&messages),
+            Path::new("messages/inbox/bob_1/message_1.json")
+        );
+        for layout in enum_iterator::all::<Layout>() {
+            assert_eq!(layout.to_logical(&layout.to_physical(&messages)), messages);
+        }
+        // uris in the JSON are already physical, they're left alone
+        assert_eq!(
+            Layout::Legacy.to_physical(Path::new("messages/inbox/bob_1/photos/1.jpg")),
+            Path::new("messages/inbox/bob_1/photos/1.jpg")
+        );
+
+        let temp = temp_dir();
+        let root = temp.path().to_path_buf();
+        std::fs::create_dir_all(root.join("your_facebook_activity/messages")).unwrap();
+        let source = DirectorySource::new(&root);
+        assert_eq!(Layout::detect(&[&source]), Some(Layout::FacebookActivity));
+        std::fs::remove_dir_all(root.join("your_facebook_activity")).unwrap();
+        std::fs::create_dir_all(root.join("something_new")).unwrap();
+        assert_eq!(Layout::detect(&[&source]), None);
+    }
+}
@@ -1,11 +1,24 @@
 use std::path::{Path, PathBuf};
-use std::process::exit;
 use std::str::FromStr;
 
 use clap::{Args, Subcommand};
 use enum_iterator::Sequence;
 
 pub mod activity;
+pub mod datasource;
+pub mod diff;
+pub mod generate;
+pub mod inventory;
+pub mod layout;
+pub mod schema;
+pub mod source;
+#[cfg(test)]
+pub(crate) mod test_util;
+
+use activity::messages::threads::ThreadCategory;
+use activity::ActivityTypes;
+use layout::Layout;
+use source::Export;
 
 #[derive(clap::Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
@@ -13,6 +26,27 @@ pub struct CliCommand {
     // #[clap(name = "activity", about = "Do something with activity data")]
     #[clap(subcommand)]
     pub command: CliCommands,
+    /// The export to read, either an extracted folder or the ZIP files from Facebook. Give it
+    /// more than once to read a download that's split across several ZIPs.
+    #[clap(short, long, env = "FACEBOOK_DATA", default_value = BASE_PATH, global = true)]
+    pub data: Vec<PathBuf>,
+    /// An older export of the same account to merge in, messages only in older exports are
+    /// kept and duplicates dropped. Give it more than once, newest first.
+    #[clap(short, long, global = true)]
+    pub merge_with: Vec<PathBuf>,
+}
+
+impl CliCommand {
+    /// Open the export, merging in any older ones
+    pub fn export(&self) -> Result<Export, MagicError> {
+        let export = Export::load(&self.data)?;
+        let older = self
+            .merge_with
+            .iter()
+            .map(|path| Export::load(std::slice::from_ref(path)))
+            .collect::<Result<Vec<Export>, MagicError>>()?;
+        Ok(export.merge(older))
+    }
 }
 
 #[derive(Subcommand, Debug)]
@@ -21,11 +55,123 @@ pub enum CliCommands {
         #[clap(subcommand)]
         command: ActivityActivity,
     },
+    /// Show what merging the exports given with --merge-with into --data gives
+    Merge,
+    /// Sort every file in the export by whether it's parsed, media, a placeholder or unknown
+    Inventory {
+        /// Output JSON instead of a report
+        #[clap(long)]
+        json: bool,
+    },
+    /// Everything from every source that has something in the export, oldest first
+    Timeline {
+        /// Only these sources, can be given more than once
+        #[clap(short, long)]
+        source: Vec<String>,
+        #[clap(short, long, value_enum, default_value_t = OutputFormat::Json)]
+        format: OutputFormat,
+        /// Write to this file instead of stdout
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Check the export against the JSON Schemas, see `export schema`
+    Validate {
+        /// Output JSON instead of a report
+        #[clap(long)]
+        json: bool,
+    },
+    /// Work with whole exports
+    Export {
+        #[clap(subcommand)]
+        command: ExportCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ExportCommand {
+    /// Compare two exports, showing what was added or deleted in the newer one
+    Diff {
+        old: PathBuf,
+        new: PathBuf,
+        /// Output JSON instead of a report
+        #[clap(long)]
+        json: bool,
+    },
+    /// Print the JSON Schemas for the export format, or write them to a folder
+    Schema { output: Option<PathBuf> },
+    /// Write every source's records out as JSON, one file per source
+    Records {
+        output: PathBuf,
+        /// Only these sources, can be given more than once
+        #[clap(short, long)]
+        source: Vec<String>,
+    },
+    /// Write a fake export to test or benchmark against
+    Generate {
+        output: PathBuf,
+        #[clap(long, default_value_t = 4)]
+        threads: usize,
+        /// Messages per thread
+        #[clap(long, default_value_t = 120)]
+        messages: usize,
+        /// Attachments per thread
+        #[clap(long, default_value_t = 20)]
+        attachments: usize,
+    },
 }
 
 #[derive(Subcommand, Debug)]
 pub enum ActivityActivity {
     Messages(ActivityMessages),
+    /// Bug bounty reports submitted, with their status and payouts
+    BugBounty {
+        /// Skip reports that don't parse instead of failing
+        #[clap(long)]
+        lenient: bool,
+        /// Write the reports out in this format, to stdout unless there's an output file
+        #[clap(short, long, value_enum)]
+        format: Option<OutputFormat>,
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Your posts, ones on other people's timelines and check-ins
+    Posts {
+        #[clap(subcommand)]
+        command: PostsSubCommand,
+        /// Skip posts that don't parse instead of failing
+        #[clap(long, global = true)]
+        lenient: bool,
+    },
+    /// Photo and video albums, outside of Messenger
+    Albums {
+        #[clap(subcommand)]
+        command: AlbumsSubCommand,
+        /// Skip photos that don't parse instead of failing
+        #[clap(long, global = true)]
+        lenient: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum AlbumsSubCommand {
+    List,
+    /// Copy the photos and videos into a folder per album, named by when they were taken, with
+    /// captions and comments in a `.json` sidecar next to each
+    Reorg {
+        #[clap(short, long, default_value = "output/albums")]
+        output: PathBuf,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum PostsSubCommand {
+    List,
+    /// Write the posts out as a static site, one page per post with the media copied over
+    Site {
+        output: PathBuf,
+        #[clap(short, long, value_enum, default_value_t = SiteFormat::Html)]
+        format: SiteFormat,
+    },
 }
 
 pub struct ActivityMessagesSearchMessages {}
@@ -35,7 +181,76 @@ pub enum ActivityMessagesSubCommand {
     ReorgImages,
     ReorgVideos,
     ListFiles,
-    SearchMessages { path: Option<PathBuf> },
+    ListThreads,
+    Stats,
+    SearchMessages {
+        path: Option<PathBuf>,
+    },
+    /// Who was in each group chat and when, plus renames, nicknames and the like
+    GroupTimeline {
+        #[clap(short, long, value_enum, default_value_t = OutputFormat::Json)]
+        format: OutputFormat,
+        /// Write to this file instead of stdout
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Every audio and video call, with totals per contact and per month
+    Calls {
+        /// Also write the calls to this CSV file
+        #[clap(long)]
+        csv: Option<PathBuf>,
+        /// Also write the calls to this iCalendar file
+        #[clap(long)]
+        ics: Option<PathBuf>,
+    },
+    /// Every link that's been shared, with per-domain stats
+    Links {
+        /// Write the links out in this format, to stdout unless there's an output file
+        #[clap(short, long, value_enum)]
+        format: Option<LinkFormat>,
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// The IP addresses each sender's messages came from, and when
+    IpTimeline {
+        /// MaxMind format database to look up country or ASN in, can be given more than once
+        #[clap(long)]
+        geoip_db: Vec<PathBuf>,
+        /// Also write the results to this CSV file
+        #[clap(long)]
+        csv: Option<PathBuf>,
+    },
+    /// Write a copy of a thread with names, text, times and attachments disguised
+    Anonymize {
+        /// Folder to write the thread to, laid out like an export
+        output: PathBuf,
+        /// Scrambling's random but repeatable, the same seed gives the same output
+        #[clap(long, default_value_t = 1)]
+        seed: u64,
+        /// How many days to move timestamps back by, picked from the seed if it's not given
+        #[clap(long)]
+        shift_days: Option<i64>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    Json,
+    Csv,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum SiteFormat {
+    Markdown,
+    Html,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum LinkFormat {
+    Json,
+    Csv,
+    /// Netscape bookmarks HTML, which browsers can import
+    Bookmarks,
 }
 
 #[derive(Args, Debug)]
@@ -44,6 +259,9 @@ pub struct ActivityMessages {
     pub command: ActivityMessagesSubCommand,
     #[clap(short, long)]
     pub target_folder: Option<String>,
+    /// Only look at threads in this category, can be given more than once
+    #[clap(short, long, value_enum)]
+    pub category: Vec<ThreadCategory>,
 }
 
 pub(crate) static BASE_PATH: &str = "data";
@@ -61,7 +279,7 @@ pub enum Folders {
 }
 
 impl Folders {
-    pub fn path(&self) -> &str {
+    pub fn path(&self) -> &'static str {
         match self {
             Folders::AdsInformation => "ads_information",
             Folders::AppsAndWebsitesOffOfFacebook => "apps_and_websites_off_of_facebook",
@@ -88,24 +306,130 @@ impl FromStr for MagicError {
     }
 }
 
-/// Checks the various folders are where we think they are
-pub fn folder_checks() {
-    let path = "data/";
-    println!("Checking all required folders exist in {}", path);
-    let path = Path::new(path);
+impl CliCommands {
+    /// The parts of the export this command can't do without
+    pub fn required_data(&self) -> Vec<ActivityTypes> {
+        match self {
+            CliCommands::Activity {
+                command: ActivityActivity::Messages(_),
+            }
+            | CliCommands::Merge => vec![ActivityTypes::Messages],
+            CliCommands::Activity {
+                command: ActivityActivity::BugBounty { .. },
+            } => vec![ActivityTypes::BugBounty],
+            CliCommands::Activity {
+                command: ActivityActivity::Posts { .. },
+            } => vec![ActivityTypes::Posts],
+            CliCommands::Activity {
+                command: ActivityActivity::Albums { .. },
+            } => vec![ActivityTypes::Albums],
+            // validate covers messages, bug bounty, posts and albums, and checks whatever's there
+            CliCommands::Inventory { .. }
+            | CliCommands::Validate { .. }
+            | CliCommands::Timeline { .. }
+            | CliCommands::Export { .. } => Vec::new(),
+        }
+    }
+}
+
+/// What [folder_checks] found
+#[derive(Debug, PartialEq, Eq)]
+pub enum FolderCheck {
+    /// Everything that's needed is there
+    Ready,
+    /// Facebook says there's no data for something that's needed, so there's nothing to do
+    NoData,
+}
+
+/// Checks the folders a command needs are in the export. Only those are required, a partial
+/// download is fine as long as it has them. Other missing top level folders are just mentioned.
+pub fn folder_checks(
+    export: &Export,
+    required: &[ActivityTypes],
+) -> Result<FolderCheck, MagicError> {
+    for part in export.exports() {
+        if part.layout().is_none() {
+            let found: Vec<String> = part
+                .top_level()
+                .iter()
+                .map(|folder| folder.display().to_string())
+                .collect();
+            let found = match found.is_empty() {
+                true => "no files".to_string(),
+                false => found.join(", "),
+            };
+            eprintln!(
+                "Unrecognised export layout, the top level has: {}. Reading it as the {} layout",
+                found,
+                Layout::default().name()
+            );
+        }
+    }
     for folder in enum_iterator::all::<Folders>() {
-        let folder_path = path.join(folder.path());
-        if !folder_path.exists() {
-            eprintln!("{} does not exist", folder_path.display());
-            exit(1)
+        if !export.exists(Path::new(folder.path())) {
+            eprintln!("{} is not in the export", folder.path());
+        }
+    }
+    let mut check = FolderCheck::Ready;
+    for activity in required {
+        if activity.skippable(export) {
+            eprintln!("No data in {}", activity.path().display());
+            check = FolderCheck::NoData;
+        } else if !export.exists(&activity.path()) {
+            return Err(MagicError::Generic(format!(
+                "{} does not exist",
+                activity.path().display()
+            )));
         }
     }
-    println!("All folders exist");
+    Ok(check)
 }
 
 pub trait Skippable {
-    /// Get the path for this type
+    /// Get the path for this type, relative to the root of the export
     fn path(&self) -> PathBuf;
     /// Is this skippable ()
-    fn skippable(&self) -> bool;
+    fn skippable(&self, export: &Export) -> bool;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{folder_checks, CliCommands, FolderCheck};
+    use crate::activity::ActivityTypes;
+    use crate::source::Export;
+    use crate::test_util::temp_dir;
+    use crate::Skippable;
+
+    #[test]
+    fn test_folder_checks() {
+        let temp = temp_dir();
+        let root = temp.path().to_path_buf();
+        // a messages only download, with nothing else in it
+        std::fs::create_dir_all(root.join(ActivityTypes::Messages.path()).join("inbox")).unwrap();
+        let export = Export::load(std::slice::from_ref(&root)).unwrap();
+
+        assert_eq!(
+            folder_checks(&export, &[ActivityTypes::Messages]).unwrap(),
+            FolderCheck::Ready
+        );
+        assert_eq!(folder_checks(&export, &[]).unwrap(), FolderCheck::Ready);
+        assert!(folder_checks(&export, &[ActivityTypes::BugBounty]).is_err());
+
+        std::fs::create_dir_all(root.join(ActivityTypes::BugBounty.path())).unwrap();
+        std::fs::write(
+            root.join(ActivityTypes::BugBounty.path())
+                .join("no-data.txt"),
+            "",
+        )
+        .unwrap();
+        assert_eq!(
+            folder_checks(&export, &[ActivityTypes::BugBounty]).unwrap(),
+            FolderCheck::NoData
+        );
+
+        // validate checks whatever's there, so a download without messages is fine
+        assert!(CliCommands::Validate { json: false }
+            .required_data()
+            .is_empty());
+    }
 }
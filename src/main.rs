@@ -1,17 +1,77 @@
+use std::process::exit;
+
 use clap::Parser;
+use facebook_data_parser::activity::albums::{list_albums, reorg_albums};
+use facebook_data_parser::activity::bug_bounty::bug_bounty_reports;
+use facebook_data_parser::activity::messages::anonymize::anonymize;
+use facebook_data_parser::activity::messages::calls::list_calls;
+use facebook_data_parser::activity::messages::ips::ip_timeline;
+use facebook_data_parser::activity::messages::links::list_links;
+use facebook_data_parser::activity::messages::merge::merge_summary;
+use facebook_data_parser::activity::messages::timeline::group_timelines;
 use facebook_data_parser::activity::messages::{
-    list_files, reorg_images, reorg_videos, search_messages,
+    list_files, list_threads, message_stats, reorg_images, reorg_videos, search_messages,
 };
+use facebook_data_parser::activity::posts::list_posts;
+use facebook_data_parser::activity::posts::site::posts_site;
+use facebook_data_parser::activity::ParseMode;
 // use enum_iterator::all;
 // use facebook_data_parser::activity::ActivityTypes;
+use facebook_data_parser::datasource::{export_records, timeline};
+use facebook_data_parser::diff::export_diff;
+use facebook_data_parser::generate::{generate, GeneratorConfig};
+use facebook_data_parser::inventory::inventory;
+use facebook_data_parser::schema::{validate, write_schemas};
 use facebook_data_parser::{
-    folder_checks, ActivityActivity, ActivityMessagesSubCommand, CliCommand, CliCommands,
+    folder_checks, ActivityActivity, ActivityMessagesSubCommand, AlbumsSubCommand, CliCommand,
+    CliCommands, ExportCommand, FolderCheck, MagicError, PostsSubCommand,
 };
 
 fn main() {
     let cliopts = CliCommand::parse();
 
-    folder_checks();
+    // these work on the exports they're given rather than --data
+    if let CliCommands::Export { command } = &cliopts.command {
+        match command {
+            ExportCommand::Diff { old, new, json } => {
+                export_diff(old, new, *json).expect("Failed to diff exports")
+            }
+            ExportCommand::Generate {
+                output,
+                threads,
+                messages,
+                attachments,
+            } => generate(
+                output,
+                &GeneratorConfig {
+                    threads: *threads,
+                    messages: *messages,
+                    attachments: *attachments,
+                },
+            )
+            .expect("Failed to generate export"),
+            ExportCommand::Records { output, source } => {
+                let export = cliopts.export().expect("Failed to open export");
+                export_records(&export, source, output).expect("Failed to export records")
+            }
+            ExportCommand::Schema { output } => {
+                write_schemas(output.as_deref()).expect("Failed to write schemas")
+            }
+        }
+        return;
+    }
+
+    let export = cliopts.export().expect("Failed to open export");
+
+    match folder_checks(&export, &cliopts.command.required_data()) {
+        Ok(FolderCheck::Ready) => {}
+        Ok(FolderCheck::NoData) => return,
+        Err(MagicError::Generic(err)) => {
+            eprintln!("{}", err);
+            exit(1)
+        }
+        Err(err) => panic!("Failed to check folders: {:?}", err),
+    }
 
     // eprintln!("CliOpts: {:?}", cliopts);
 
@@ -20,20 +80,98 @@ fn main() {
             ActivityActivity::Messages(msg) => {
                 match msg.command {
                     ActivityMessagesSubCommand::ReorgImages => {
-                        reorg_images(msg).expect("Failed to reorg messages");
+                        reorg_images(&export, msg).expect("Failed to reorg messages");
                     }
                     ActivityMessagesSubCommand::ReorgVideos => {
-                        reorg_videos(msg).expect("Failed to reorg videos");
+                        reorg_videos(&export, msg).expect("Failed to reorg videos");
                     }
                     ActivityMessagesSubCommand::ListFiles => {
-                        list_files(msg).expect("Failed to list files")
+                        list_files(&export, msg).expect("Failed to list files")
+                    }
+                    ActivityMessagesSubCommand::ListThreads => {
+                        list_threads(&export, msg).expect("Failed to list threads")
+                    }
+                    ActivityMessagesSubCommand::Stats => {
+                        message_stats(&export, msg).expect("Failed to get message stats")
                     }
                     ActivityMessagesSubCommand::SearchMessages { path } => {
-                        search_messages(path).expect("Failed to search messages")
+                        search_messages(&export, path, &msg.category)
+                            .expect("Failed to search messages")
+                    }
+                    ActivityMessagesSubCommand::GroupTimeline { format, ref output } => {
+                        let output = output.clone();
+                        group_timelines(&export, msg, format, output)
+                            .expect("Failed to build group timelines")
+                    }
+                    ActivityMessagesSubCommand::Calls { ref csv, ref ics } => {
+                        let (csv, ics) = (csv.clone(), ics.clone());
+                        list_calls(&export, msg, csv, ics).expect("Failed to list calls")
+                    }
+                    ActivityMessagesSubCommand::Links { format, ref output } => {
+                        let output = output.clone();
+                        list_links(&export, msg, format, output).expect("Failed to list links")
+                    }
+                    ActivityMessagesSubCommand::IpTimeline {
+                        ref geoip_db,
+                        ref csv,
+                    } => {
+                        let (geoip_db, csv) = (geoip_db.clone(), csv.clone());
+                        ip_timeline(&export, msg, geoip_db, csv)
+                            .expect("Failed to build IP timeline")
+                    }
+                    ActivityMessagesSubCommand::Anonymize {
+                        ref output,
+                        seed,
+                        shift_days,
+                    } => {
+                        let output = output.clone();
+                        anonymize(&export, msg, output, seed, shift_days)
+                            .expect("Failed to anonymize thread")
+                    }
+                }
+                // reorg_images(&export, msg).expect("Failed to reorg messages");
+            }
+            ActivityActivity::BugBounty {
+                lenient,
+                format,
+                output,
+            } => bug_bounty_reports(&export, lenient.into(), format, output)
+                .expect("Failed to read bug bounty reports"),
+            ActivityActivity::Posts { command, lenient } => {
+                let mode = ParseMode::from(lenient);
+                match command {
+                    PostsSubCommand::List => {
+                        list_posts(&export, mode).expect("Failed to list posts")
+                    }
+                    PostsSubCommand::Site { output, format } => {
+                        posts_site(&export, mode, output, format).expect("Failed to write site")
+                    }
+                }
+            }
+            ActivityActivity::Albums { command, lenient } => {
+                let mode = ParseMode::from(lenient);
+                match command {
+                    AlbumsSubCommand::List => {
+                        list_albums(&export, mode).expect("Failed to list albums")
+                    }
+                    AlbumsSubCommand::Reorg { output } => {
+                        reorg_albums(&export, mode, output).expect("Failed to reorg albums")
                     }
                 }
-                // reorg_images(msg).expect("Failed to reorg messages");
             }
         },
+        CliCommands::Merge => merge_summary(&export).expect("Failed to merge exports"),
+        CliCommands::Timeline {
+            ref source,
+            format,
+            ref output,
+        } => timeline(&export, source, format, output.clone()).expect("Failed to build timeline"),
+        CliCommands::Validate { json } => {
+            validate(&export, json).expect("Failed to validate export")
+        }
+        CliCommands::Inventory { json } => {
+            inventory(&export, json).expect("Failed to take inventory")
+        }
+        CliCommands::Export { .. } => unreachable!("handled before the export's opened"),
     }
 }
@@ -0,0 +1,253 @@
+//!
+//! JSON Schemas for the export, generated from the types that parse it so they can't drift
+//!
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use jsonschema::JSONSchema;
+use rayon::prelude::*;
+use schemars::schema::RootSchema;
+use schemars::schema_for;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::activity::albums::{Album, AlbumsSource};
+use crate::activity::bug_bounty::{BugBountyFile, BugBountySource};
+use crate::activity::messages::e2ee::{E2eeMessageFileParser, MessageFileFormat};
+use crate::activity::messages::{find_message_files, MessageFileParser};
+use crate::activity::posts::{PostsFile, PostsSource};
+use crate::activity::ActivityTypes;
+use crate::datasource::DataSource;
+use crate::source::Export;
+use crate::{MagicError, Skippable};
+
+/// Every schema, by name
+pub fn schemas() -> BTreeMap<&'static str, RootSchema> {
+    BTreeMap::from([
+        ("message_file", schema_for!(MessageFileParser)),
+        ("e2ee_message_file", schema_for!(E2eeMessageFileParser)),
+        ("bug_bounty_file", schema_for!(BugBountyFile)),
+        ("posts_file", schema_for!(PostsFile)),
+        ("album_file", schema_for!(Album)),
+    ])
+}
+
+/// Which schema a file in the export should match, if there is one for it
+pub fn schema_name(path: &Path, value: &Value) -> Option<&'static str> {
+    if path.extension().is_none_or(|ext| ext != "json") {
+        return None;
+    }
+    if path.starts_with(ActivityTypes::Messages.path()) {
+        let format = value
+            .as_object()?
+            .iter()
+            .find_map(|(key, value)| MessageFileFormat::detect(key, value))
+            .unwrap_or_default();
+        return Some(match format {
+            MessageFileFormat::Legacy => "message_file",
+            MessageFileFormat::E2ee => "e2ee_message_file",
+        });
+    }
+    if path.starts_with(ActivityTypes::BugBounty.path()) {
+        return Some("bug_bounty_file");
+    }
+    if DataSource::handles(&PostsSource, path) {
+        return Some("posts_file");
+    }
+    if DataSource::handles(&AlbumsSource, path) {
+        return Some("album_file");
+    }
+    None
+}
+
+/// The files there are schemas for, which leaves out HTML message files
+fn schema_files(export: &Export) -> Vec<PathBuf> {
+    let mut files = find_message_files(export, &ActivityTypes::Messages.path());
+    files.extend(DataSource::discover(&BugBountySource, export));
+    files.extend(DataSource::discover(&PostsSource, export));
+    files.extend(DataSource::discover(&AlbumsSource, export));
+    files.retain(|path| path.extension().is_some_and(|ext| ext == "json"));
+    files
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub file: PathBuf,
+    /// JSON pointer to the value that's wrong, `/messages/3/sender_name` or the like
+    pub pointer: String,
+    pub message: String,
+}
+
+pub struct Validator {
+    compiled: BTreeMap<&'static str, JSONSchema>,
+}
+
+impl Validator {
+    pub fn new() -> Result<Self, MagicError> {
+        let compiled = schemas()
+            .into_iter()
+            .map(|(name, schema)| {
+                let schema = serde_json::to_value(schema)
+                    .map_err(|err| MagicError::Generic(err.to_string()))?;
+                let compiled = JSONSchema::compile(&schema).map_err(|err| {
+                    MagicError::Generic(format!("Invalid schema {}: {}", name, err))
+                })?;
+                Ok((name, compiled))
+            })
+            .collect::<Result<BTreeMap<_, _>, MagicError>>()?;
+        Ok(Self { compiled })
+    }
+
+    /// Checks a file's contents, `None` if there's no schema for it
+    pub fn validate(&self, path: &Path, value: &Value) -> Option<Vec<Violation>> {
+        let schema = self.compiled.get(schema_name(path, value)?)?;
+        let violations = match schema.validate(value) {
+            Ok(()) => Vec::new(),
+            Err(errors) => errors
+                .map(|error| Violation {
+                    file: path.to_path_buf(),
+                    pointer: error.instance_path.to_string(),
+                    message: error.to_string(),
+                })
+                .collect(),
+        };
+        Some(violations)
+    }
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct ValidationReport {
+    pub files_checked: usize,
+    pub violations: Vec<Violation>,
+}
+
+pub fn validate_export(export: &Export) -> Result<ValidationReport, MagicError> {
+    let validator = Validator::new()?;
+    let results = schema_files(export)
+        .par_iter()
+        .map(|path| {
+            let value: Value = match serde_json::from_reader(export.open(path)?) {
+                Ok(value) => value,
+                Err(err) => {
+                    return Ok(Some(vec![Violation {
+                        file: path.clone(),
+                        pointer: String::new(),
+                        message: format!("Not valid JSON: {}", err),
+                    }]))
+                }
+            };
+            Ok(validator.validate(path, &value))
+        })
+        .collect::<Result<Vec<Option<Vec<Violation>>>, MagicError>>()?;
+
+    let mut report = ValidationReport::default();
+    for violations in results.into_iter().flatten() {
+        report.files_checked += 1;
+        report.violations.extend(violations);
+    }
+    Ok(report)
+}
+
+/// Print every schema, or write them to `<name>.schema.json` in a folder
+pub fn write_schemas(output: Option<&Path>) -> Result<(), MagicError> {
+    let schemas = schemas();
+    match output {
+        Some(folder) => {
+            std::fs::create_dir_all(folder)
+                .map_err(|err| MagicError::Generic(format!("{}: {}", folder.display(), err)))?;
+            for (name, schema) in schemas.iter() {
+                let path = folder.join(format!("{}.schema.json", name));
+                let contents = serde_json::to_string_pretty(schema)
+                    .map_err(|err| MagicError::Generic(err.to_string()))?;
+                std::fs::write(&path, contents)
+                    .map_err(|err| MagicError::Generic(format!("{}: {}", path.display(), err)))?;
+                println!("Wrote {}", path.display());
+            }
+        }
+        None => {
+            let contents = serde_json::to_string_pretty(&schemas)
+                .map_err(|err| MagicError::Generic(err.to_string()))?;
+            println!("{}", contents);
+        }
+    }
+    Ok(())
+}
+
+pub fn validate(export: &Export, json: bool) -> Result<(), MagicError> {
+    let report = validate_export(export)?;
+    if json {
+        let output = serde_json::to_string_pretty(&report)
+            .map_err(|err| MagicError::Generic(err.to_string()))?;
+        println!("{}", output);
+    } else {
+        for violation in report.violations.iter() {
+            println!(
+                "{}: {}: {}",
+                violation.file.display(),
+                if violation.pointer.is_empty() {
+                    "/"
+                } else {
+                    &violation.pointer
+                },
+                violation.message
+            );
+        }
+        println!(
+            "Checked {} files, found {} problems",
+            report.files_checked,
+            report.violations.len()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use serde_json::json;
+
+    use super::{validate_export, Validator};
+    use crate::source::{DirectorySource, Export};
+    use crate::test_util::{message_file, temp_dir, write_file};
+
+    #[test]
+    fn test_validator() {
+        let validator = Validator::new().unwrap();
+        let path = Path::new("your_activity_across_facebook/messages/inbox/bob_1/message_1.json");
+        let mut file = message_file(
+            "inbox/bob_1",
+            json!([
+                {"sender_name": "Bob", "timestamp_ms": 1, "content": "hi", "is_geoblocked_for_viewer": false}
+            ]),
+        );
+        assert_eq!(validator.validate(path, &file), Some(Vec::new()));
+
+        file["messages"][0]["timestamp_ms"] = json!("yesterday");
+        file["messages"][0]["surprise"] = json!(true);
+        let violations = validator.validate(path, &file).unwrap();
+        let pointers: Vec<&str> = violations.iter().map(|v| v.pointer.as_str()).collect();
+        assert!(pointers.contains(&"/messages/0/timestamp_ms"));
+        assert!(pointers.contains(&"/messages/0"));
+
+        assert_eq!(
+            validator.validate(Path::new("posts/your_posts_1.html"), &file),
+            None
+        );
+    }
+
+    #[test]
+    fn test_validate_export() {
+        let temp = temp_dir();
+        let root = temp.path().to_path_buf();
+        let folder = Path::new("your_activity_across_facebook/messages/inbox/bob_1");
+        write_file(&root, folder.join("message_1.html"), "<html></html>");
+        write_file(&root, folder.join("message_2.json"), "{");
+
+        let report = validate_export(&Export::from_source(DirectorySource::new(&root))).unwrap();
+        // the HTML file's not checked, the broken JSON one is
+        assert_eq!(report.files_checked, 1);
+        assert_eq!(report.violations.len(), 1);
+        assert!(report.violations[0].file.ends_with("message_2.json"));
+    }
+}
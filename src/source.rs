@@ -0,0 +1,587 @@
+//!
+//! Where the export's files come from - an extracted folder, or the ZIP files Facebook sends
+//!
+//! All paths handed to an [Export] are relative to the root of the download, the same way the
+//! `uri` fields in the JSON files are, eg `your_activity_across_facebook/messages/inbox/...`
+//!
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use flate2::read::DeflateDecoder;
+use rayon::prelude::*;
+use zip::{CompressionMethod, ZipArchive};
+
+use crate::layout::Layout;
+use crate::MagicError;
+
+pub trait ExportSource: Debug + Send + Sync {
+    /// Is there a file or folder at this path
+    fn exists(&self, path: &Path) -> bool;
+    /// Every file under this folder, at any depth
+    fn files(&self, folder: &Path) -> Vec<PathBuf>;
+    /// Open a file for reading
+    fn open(&self, path: &Path) -> Result<Box<dyn Read + Send>, MagicError>;
+    /// How big a file is, in bytes
+    fn size(&self, path: &Path) -> Result<u64, MagicError>;
+    /// Where this source is on disk, if it's been extracted
+    fn root(&self) -> Option<&Path> {
+        None
+    }
+}
+
+/// An export that's been extracted to a folder
+#[derive(Debug)]
+pub struct DirectorySource {
+    pub root: PathBuf,
+}
+
+impl DirectorySource {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// Walks a folder tree in parallel, returning every file in it
+    fn walk(&self, folder: &Path) -> Vec<PathBuf> {
+        let entries = match std::fs::read_dir(self.root.join(folder)) {
+            Ok(entries) => entries,
+            Err(err) => {
+                eprintln!("Failed to read {}: {:?}", folder.display(), err);
+                return Vec::new();
+            }
+        };
+        let mut files = Vec::new();
+        let mut subfolders = Vec::new();
+        for entry in entries.flatten() {
+            let path = folder.join(entry.file_name());
+            match entry.file_type() {
+                Ok(filetype) if filetype.is_dir() => subfolders.push(path),
+                Ok(_) => files.push(path),
+                Err(err) => eprintln!("Failed to read {}: {:?}", path.display(), err),
+            }
+        }
+        files.par_extend(
+            subfolders
+                .par_iter()
+                .flat_map(|subfolder| self.walk(subfolder)),
+        );
+        files
+    }
+}
+
+impl ExportSource for DirectorySource {
+    fn exists(&self, path: &Path) -> bool {
+        self.root.join(path).exists()
+    }
+
+    fn files(&self, folder: &Path) -> Vec<PathBuf> {
+        if !self.root.join(folder).is_dir() {
+            return Vec::new();
+        }
+        self.walk(folder)
+    }
+
+    fn open(&self, path: &Path) -> Result<Box<dyn Read + Send>, MagicError> {
+        let fullpath = self.root.join(path);
+        let file = File::open(&fullpath)
+            .map_err(|err| MagicError::Generic(format!("{}: {}", fullpath.display(), err)))?;
+        Ok(Box::new(BufReader::new(file)))
+    }
+
+    fn size(&self, path: &Path) -> Result<u64, MagicError> {
+        let fullpath = self.root.join(path);
+        std::fs::metadata(&fullpath)
+            .map(|metadata| metadata.len())
+            .map_err(|err| MagicError::Generic(format!("{}: {}", fullpath.display(), err)))
+    }
+
+    fn root(&self) -> Option<&Path> {
+        Some(&self.root)
+    }
+}
+
+#[derive(Debug)]
+struct ZipEntry {
+    archive: usize,
+    name: String,
+}
+
+/// One entry's compressed bytes, read straight from the ZIP file at their own offset so several
+/// entries can be streamed at once without holding the archive's lock
+#[derive(Debug)]
+struct EntryReader {
+    file: Arc<File>,
+    position: u64,
+    end: u64,
+}
+
+impl Read for EntryReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = buf.len().min((self.end - self.position) as usize);
+        if len == 0 {
+            return Ok(0);
+        }
+        let read = read_at(&self.file, &mut buf[..len], self.position)?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+#[cfg(unix)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+    std::os::unix::fs::FileExt::read_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+    std::os::windows::fs::FileExt::seek_read(file, buf, offset)
+}
+
+#[derive(Debug)]
+struct Archive {
+    path: PathBuf,
+    /// Only used for looking up entries
+    index: Mutex<ZipArchive<File>>,
+    /// A separate handle for reading entries, so it doesn't move the index's position
+    data: Arc<File>,
+}
+
+/// One or more ZIP files, read without extracting them. Facebook splits big downloads into
+/// several archives, they're treated as one tree.
+#[derive(Debug)]
+pub struct ZipSource {
+    archives: Vec<Archive>,
+    entries: BTreeMap<PathBuf, ZipEntry>,
+    folders: BTreeSet<PathBuf>,
+}
+
+impl ZipSource {
+    pub fn new(paths: &[PathBuf]) -> Result<Self, MagicError> {
+        let mut archives = Vec::new();
+        let mut names: Vec<(usize, String)> = Vec::new();
+        for path in paths {
+            let open = || {
+                File::open(path)
+                    .map_err(|err| MagicError::Generic(format!("{}: {}", path.display(), err)))
+            };
+            let archive = ZipArchive::new(open()?)
+                .map_err(|err| MagicError::Generic(format!("{}: {}", path.display(), err)))?;
+            names.extend(
+                archive
+                    .file_names()
+                    .filter(|name| !name.ends_with('/'))
+                    .map(|name| (archives.len(), name.to_string())),
+            );
+            archives.push(Archive {
+                path: path.clone(),
+                index: Mutex::new(archive),
+                data: Arc::new(open()?),
+            });
+        }
+
+        // some downloads wrap everything in a single folder, look through it if that's the case
+        let has_known_folder = names.iter().any(|(_, name)| {
+            Layout::known_top_level()
+                .iter()
+                .any(|folder| name.starts_with(&format!("{}/", folder)))
+        });
+        let prefix = match names.first() {
+            Some((_, first)) if !has_known_folder => first
+                .split_once('/')
+                .map(|(top, _)| format!("{}/", top))
+                .filter(|top| names.iter().all(|(_, name)| name.starts_with(top))),
+            _ => None,
+        };
+
+        let mut entries = BTreeMap::new();
+        let mut folders = BTreeSet::new();
+        for (archive, name) in names {
+            let path = PathBuf::from(match &prefix {
+                Some(prefix) => name.strip_prefix(prefix.as_str()).unwrap_or(&name),
+                None => &name,
+            });
+            folders.extend(path.ancestors().skip(1).map(Path::to_path_buf));
+            entries.insert(path, ZipEntry { archive, name });
+        }
+        Ok(Self {
+            archives,
+            entries,
+            folders,
+        })
+    }
+}
+
+impl ExportSource for ZipSource {
+    fn exists(&self, path: &Path) -> bool {
+        self.entries.contains_key(path) || self.folders.contains(path)
+    }
+
+    fn files(&self, folder: &Path) -> Vec<PathBuf> {
+        self.entries
+            .range(folder.to_path_buf()..)
+            .take_while(|(path, _)| path.starts_with(folder))
+            .map(|(path, _)| path.clone())
+            .collect()
+    }
+
+    /// The archive can only read one entry at a time, so the lock's only held to find where the
+    /// entry's data is, then it's streamed from there on its own handle.
+    fn open(&self, path: &Path) -> Result<Box<dyn Read + Send>, MagicError> {
+        let entry = self
+            .entries
+            .get(path)
+            .ok_or_else(|| MagicError::Generic(format!("{} not found in zip", path.display())))?;
+        let archive = &self.archives[entry.archive];
+        let error = |err: &dyn std::fmt::Display| {
+            MagicError::Generic(format!(
+                "{}:{}: {}",
+                archive.path.display(),
+                entry.name,
+                err
+            ))
+        };
+        let (start, size, compression) = {
+            let mut index = archive.index.lock().map_err(|err| {
+                MagicError::Generic(format!("{}: {}", archive.path.display(), err))
+            })?;
+            let file = index.by_name(&entry.name).map_err(|err| error(&err))?;
+            (
+                file.data_start(),
+                file.compressed_size(),
+                file.compression(),
+            )
+        };
+        let reader = BufReader::new(EntryReader {
+            file: archive.data.clone(),
+            position: start,
+            end: start + size,
+        });
+        match compression {
+            CompressionMethod::Stored => Ok(Box::new(reader)),
+            CompressionMethod::Deflated => Ok(Box::new(DeflateDecoder::new(reader))),
+            other => Err(error(&format!("unsupported compression {:?}", other))),
+        }
+    }
+
+    fn size(&self, path: &Path) -> Result<u64, MagicError> {
+        let entry = self
+            .entries
+            .get(path)
+            .ok_or_else(|| MagicError::Generic(format!("{} not found in zip", path.display())))?;
+        let archive = &self.archives[entry.archive];
+        let mut index = archive
+            .index
+            .lock()
+            .map_err(|err| MagicError::Generic(format!("{}: {}", archive.path.display(), err)))?;
+        let file = index.by_name(&entry.name).map_err(|err| {
+            MagicError::Generic(format!(
+                "{}:{}: {}",
+                archive.path.display(),
+                entry.name,
+                err
+            ))
+        })?;
+        Ok(file.size())
+    }
+}
+
+/// File extensions for photos, videos and audio
+static MEDIA_EXTENSIONS: [&str; 16] = [
+    "jpg", "jpeg", "png", "gif", "webp", "heic", "bmp", "mp4", "mov", "webm", "3gp", "mp3", "m4a",
+    "aac", "ogg", "wav",
+];
+
+pub fn is_media_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| MEDIA_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+fn is_zip(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.eq_ignore_ascii_case("zip"))
+        .unwrap_or(false)
+}
+
+/// A Facebook download, made up of one or more sources
+///
+/// Older downloads of the same account can be merged in, files are then looked for in this one
+/// first and then each older one in turn.
+///
+/// Paths are always in the [Layout::ActivityAcrossFacebook] layout, they're mapped onto each
+/// export's own layout when it's read.
+#[derive(Debug, Clone)]
+pub struct Export {
+    layers: Vec<Arc<dyn ExportSource>>,
+    /// `None` if it's not a layout we know, it's then read as the default one
+    layout: Option<Layout>,
+    merged: Vec<Export>,
+}
+
+impl Export {
+    /// Build an export from the paths given on the command line.
+    ///
+    /// Folders are read as an extracted export, unless they hold nothing but ZIP files, in which
+    /// case the ZIPs are used. ZIP files can also be listed directly, all the ZIPs given are read
+    /// as one split download.
+    pub fn load(paths: &[PathBuf]) -> Result<Self, MagicError> {
+        let mut layers: Vec<Arc<dyn ExportSource>> = Vec::new();
+        let mut zips = Vec::new();
+        for path in paths {
+            if path.is_dir() {
+                let children: Vec<PathBuf> = std::fs::read_dir(path)
+                    .map_err(|err| MagicError::Generic(format!("{}: {}", path.display(), err)))?
+                    .flatten()
+                    .map(|entry| entry.path())
+                    .filter(|child| child.file_name().is_none_or(|name| name != ".gitkeeper"))
+                    .collect();
+                if !children.is_empty() && children.iter().all(|child| is_zip(child)) {
+                    zips.extend(children);
+                } else {
+                    layers.push(Arc::new(DirectorySource::new(path)));
+                }
+            } else if is_zip(path) {
+                zips.push(path.clone());
+            } else {
+                return Err(MagicError::Generic(format!(
+                    "{} is not a folder or zip file",
+                    path.display()
+                )));
+            }
+        }
+        if !zips.is_empty() {
+            zips.sort();
+            layers.push(Arc::new(ZipSource::new(&zips)?));
+        }
+        Ok(Self::from_layers(layers))
+    }
+
+    pub fn from_source(source: impl ExportSource + 'static) -> Self {
+        Self::from_layers(vec![Arc::new(source)])
+    }
+
+    fn from_layers(layers: Vec<Arc<dyn ExportSource>>) -> Self {
+        let sources: Vec<&dyn ExportSource> = layers.iter().map(|layer| layer.as_ref()).collect();
+        Self {
+            layout: Layout::detect(&sources),
+            layers,
+            merged: Vec::new(),
+        }
+    }
+
+    /// The layout of this export, not any merged into it
+    pub fn layout(&self) -> Option<Layout> {
+        self.layout
+    }
+
+    /// The top level folders across the sources, for saying what's there when the layout's not
+    /// one we know
+    pub fn top_level(&self) -> BTreeSet<PathBuf> {
+        self.files(Path::new(""))
+            .iter()
+            .filter_map(|path| path.components().next())
+            .map(|component| PathBuf::from(component.as_os_str()))
+            .collect()
+    }
+
+    /// Merge older exports of the same account into this one, `older` should be newest first
+    pub fn merge(mut self, older: Vec<Export>) -> Self {
+        for export in older {
+            self.merged.extend(export.exports());
+        }
+        self
+    }
+
+    /// Has anything been merged into this export
+    pub fn is_merged(&self) -> bool {
+        !self.merged.is_empty()
+    }
+
+    /// The individual exports that make up this one, newest first
+    pub fn exports(&self) -> Vec<Export> {
+        let mut exports = vec![Export {
+            layers: self.layers.clone(),
+            layout: self.layout,
+            merged: Vec::new(),
+        }];
+        exports.extend(self.merged.iter().cloned());
+        exports
+    }
+
+    /// Every source, along with the layout of the export it's from
+    fn all_layers(&self) -> impl Iterator<Item = (Layout, &Arc<dyn ExportSource>)> {
+        std::iter::once(self)
+            .chain(self.merged.iter())
+            .flat_map(|export| {
+                let layout = export.layout.unwrap_or_default();
+                export.layers.iter().map(move |layer| (layout, layer))
+            })
+    }
+
+    /// The first source that has this path, and where it is in that source
+    fn find(&self, path: &Path) -> Option<(&Arc<dyn ExportSource>, PathBuf)> {
+        self.all_layers().find_map(|(layout, layer)| {
+            let physical = layout.to_physical(path);
+            layer.exists(&physical).then_some((layer, physical))
+        })
+    }
+
+    pub fn exists(&self, path: &Path) -> bool {
+        self.find(path).is_some()
+    }
+
+    /// Every file under this folder across all the sources, sorted
+    pub fn files(&self, folder: &Path) -> Vec<PathBuf> {
+        let files: BTreeSet<PathBuf> = self
+            .all_layers()
+            .flat_map(|(layout, layer)| {
+                layer
+                    .files(&layout.to_physical(folder))
+                    .into_iter()
+                    .map(move |path| layout.to_logical(&path))
+            })
+            .collect();
+        files.into_iter().collect()
+    }
+
+    pub fn open(&self, path: &Path) -> Result<Box<dyn Read + Send>, MagicError> {
+        match self.find(path) {
+            Some((layer, physical)) => layer.open(&physical),
+            None => Err(MagicError::Generic(format!(
+                "{} not found in export",
+                path.display()
+            ))),
+        }
+    }
+
+    pub fn size(&self, path: &Path) -> Result<u64, MagicError> {
+        match self.find(path) {
+            Some((layer, physical)) => layer.size(&physical),
+            None => Err(MagicError::Generic(format!(
+                "{} not found in export",
+                path.display()
+            ))),
+        }
+    }
+
+    /// Turns a path from the user into one relative to the export, so `data/messages/inbox/foo`
+    /// works as well as `messages/inbox/foo`
+    pub fn relative_path(&self, path: &Path) -> PathBuf {
+        for (layout, layer) in self.all_layers() {
+            if let Some(relative) = layer.root().and_then(|root| path.strip_prefix(root).ok()) {
+                return layout.to_logical(relative);
+            }
+        }
+        self.layout.unwrap_or_default().to_logical(path)
+    }
+
+    /// Copy a file out of the export to somewhere on disk
+    pub fn copy(&self, path: &Path, target: &Path) -> Result<u64, MagicError> {
+        let mut reader = self.open(path)?;
+        let mut file = File::create(target)
+            .map_err(|err| MagicError::Generic(format!("{}: {}", target.display(), err)))?;
+        std::io::copy(&mut reader, &mut file)
+            .map_err(|err| MagicError::Generic(format!("{}: {}", target.display(), err)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::path::{Path, PathBuf};
+
+    use zip::write::FileOptions;
+
+    use super::Export;
+    use crate::test_util::temp_dir;
+
+    fn write_zip(path: &Path, files: &[(&str, &str)]) {
+        write_zip_with(path, files, FileOptions::default());
+    }
+
+    fn write_zip_with(path: &Path, files: &[(&str, &str)], options: FileOptions) {
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(path).unwrap());
+        for (name, contents) in files {
+            zip.start_file(*name, options).unwrap();
+            zip.write_all(contents.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    #[test]
+    fn test_split_zip_export() {
+        let temp = temp_dir();
+        let folder = temp.path().to_path_buf();
+        write_zip(
+            &folder.join("part1.zip"),
+            &[("facebook-test/ads_information/no-data.txt", "")],
+        );
+        write_zip(
+            &folder.join("part2.zip"),
+            &[(
+                "facebook-test/your_activity_across_facebook/messages/inbox/bob_123/message_1.json",
+                "{}",
+            )],
+        );
+
+        let export = Export::load(std::slice::from_ref(&folder)).unwrap();
+        assert!(export.exists(Path::new("ads_information")));
+        assert!(export.exists(Path::new("your_activity_across_facebook/messages/inbox")));
+        assert_eq!(
+            export.files(Path::new("your_activity_across_facebook/messages")),
+            vec![PathBuf::from(
+                "your_activity_across_facebook/messages/inbox/bob_123/message_1.json"
+            )]
+        );
+        let mut contents = String::new();
+        export
+            .open(Path::new(
+                "your_activity_across_facebook/messages/inbox/bob_123/message_1.json",
+            ))
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        assert_eq!(contents, "{}");
+    }
+
+    #[test]
+    fn test_zip_entries_stream() {
+        let temp = temp_dir();
+        let folder = temp.path().to_path_buf();
+        let big = "0123456789".repeat(100_000);
+        write_zip(
+            &folder.join("deflated.zip"),
+            &[("posts/a.txt", big.as_str()), ("posts/b.txt", "b")],
+        );
+        write_zip_with(
+            &folder.join("stored.zip"),
+            &[("posts/c.txt", big.as_str())],
+            FileOptions::default().compression_method(zip::CompressionMethod::Stored),
+        );
+        let export =
+            Export::load(&[folder.join("deflated.zip"), folder.join("stored.zip")]).unwrap();
+
+        // two entries open at once each keep their own place
+        let mut a = export.open(Path::new("posts/a.txt")).unwrap();
+        let mut c = export.open(Path::new("posts/c.txt")).unwrap();
+        let (mut from_a, mut from_c) = (vec![0; 10], vec![0; 10]);
+        a.read_exact(&mut from_a).unwrap();
+        c.read_exact(&mut from_c).unwrap();
+        assert_eq!(from_a, b"0123456789");
+        assert_eq!(from_c, b"0123456789");
+        let mut rest = String::new();
+        a.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest.len(), big.len() - 10);
+        let mut b = String::new();
+        export
+            .open(Path::new("posts/b.txt"))
+            .unwrap()
+            .read_to_string(&mut b)
+            .unwrap();
+        assert_eq!(b, "b");
+    }
+}
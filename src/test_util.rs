@@ -0,0 +1,50 @@
+//!
+//! Fixtures shared between the tests
+//!
+use std::path::Path;
+
+use serde_json::{json, Value};
+use tempfile::TempDir;
+
+use crate::activity::messages::Message;
+
+/// Somewhere to write a test export, it's removed when dropped even if the test fails
+pub fn temp_dir() -> TempDir {
+    tempfile::Builder::new().prefix("fdp_").tempdir().unwrap()
+}
+
+/// Writes a file under `root`, making the folders it's in
+pub fn write_file(root: &Path, path: impl AsRef<Path>, contents: impl AsRef<[u8]>) {
+    let path = root.join(path);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).unwrap();
+    }
+    std::fs::write(&path, contents).unwrap();
+}
+
+/// A message from Alice, with `fields` set over the top
+pub fn message(fields: Value) -> Message {
+    let mut message = json!({
+        "sender_name": "Alice",
+        "timestamp_ms": 1,
+        "is_geoblocked_for_viewer": false,
+    });
+    if let Some(fields) = fields.as_object() {
+        for (key, value) in fields {
+            message[key] = value.clone();
+        }
+    }
+    serde_json::from_value(message).unwrap()
+}
+
+/// A message file with Bob in it, as JSON, `thread_path` is eg `inbox/bob_1`
+pub fn message_file(thread_path: &str, messages: Value) -> Value {
+    json!({
+        "participants": [{"name": "Bob"}],
+        "messages": messages,
+        "title": "Bob",
+        "is_still_participant": true,
+        "thread_path": thread_path,
+        "magic_words": []
+    })
+}